@@ -1,4 +1,4 @@
-use codspeed_criterion_compat::{criterion_group, criterion_main, Criterion};
+use codspeed_criterion_compat::{criterion_group, criterion_main, BatchSize, Criterion};
 use egglog::EGraph;
 
 fn run_example(filename: &str, program: &str, no_messages: bool) {
@@ -28,5 +28,150 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     }
 }
 
+/// Rebuilding a table keyed by many function values whose partial args all get unioned.
+/// Compare the serial and parallel canonicalization by running this with and without
+/// `--features parallel`.
+pub fn rebuild_function_values(c: &mut Criterion) {
+    let n = 20_000;
+    let mut setup = String::from(
+        "(datatype Math (Num i64) (Add Math Math))
+        (sort MathFn (UnstableFn (Math) Math))
+        (function cached (MathFn) i64 :merge (min old new))\n",
+    );
+    for i in 0..n {
+        setup.push_str(&format!(
+            "(set (cached (unstable-fn \"Add\" (Num {i}))) {i})\n"
+        ));
+    }
+    let unions = "(rule ((= x (Num n)) (!= n 0)) ((union x (Num 0))))
+        (run 1)
+        (check (= (cached (unstable-fn \"Add\" (Num 0))) 0))";
+    c.bench_function("rebuild-function-values", |b| {
+        b.iter_batched(
+            || {
+                let mut egraph = EGraph::default();
+                egraph.parse_and_run_program(None, &setup).unwrap();
+                egraph
+            },
+            |mut egraph| egraph.parse_and_run_program(None, unions).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// Like `rebuild_function_values`, but the partial args are all primitives, so even
+/// though the sort takes an eqsort input, canonicalizing the keys is skipped.
+pub fn rebuild_primitive_function_values(c: &mut Criterion) {
+    let n = 20_000;
+    let mut setup = String::from(
+        "(datatype Math (Num i64) (Scale i64 Math))
+        (sort MathFn (UnstableFn (Math) Math))
+        (function cached (MathFn) i64 :merge (min old new))\n",
+    );
+    for i in 0..n {
+        setup.push_str(&format!("(set (cached (unstable-fn \"Scale\" {i})) {i})\n"));
+    }
+    let unions = "(rule ((= x (Num n)) (!= n 0)) ((union x (Num 0))))
+        (Num 1)
+        (Num 2)
+        (run 1)
+        (check (= (cached (unstable-fn \"Scale\" 0)) 0))";
+    c.bench_function("rebuild-primitive-function-values", |b| {
+        b.iter_batched(
+            || {
+                let mut egraph = EGraph::default();
+                egraph.parse_and_run_program(None, &setup).unwrap();
+                egraph
+            },
+            |mut egraph| egraph.parse_and_run_program(None, unions).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// Applying a function value that wraps a primitive to many ints, which skips the action
+/// machinery since no e-classes are involved.
+pub fn apply_primitive_function_values(c: &mut Criterion) {
+    let n = 20_000;
+    let ints: Vec<String> = (0..n).map(|i| i.to_string()).collect();
+    let setup = format!(
+        "(sort IntFn (UnstableFn (i64) i64))
+        (sort IntSet (Set i64))
+        (let xs (set-of {}))",
+        ints.join(" ")
+    );
+    let apply = "(let ys (set-map (unstable-fn \"+\" 1) xs))";
+    c.bench_function("apply-primitive-function-values", |b| {
+        b.iter_batched(
+            || {
+                let mut egraph = EGraph::default();
+                egraph.parse_and_run_program(None, &setup).unwrap();
+                egraph
+            },
+            |mut egraph| egraph.parse_and_run_program(None, apply).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// Applying one function value that wraps a constructor many times in a row, which compiles
+/// the call once and reuses it for every step.
+pub fn apply_function_value_repeatedly(c: &mut Criterion) {
+    let setup = "(datatype Math (Num i64) (Neg Math))
+        (sort MathFn (UnstableFn (Math) Math))
+        (sort MathVec (Vec Math))
+        (let neg-fn (unstable-fn \"Neg\"))";
+    let apply = "(let trace (apply-trace neg-fn (Num 0) 10000))";
+    c.bench_function("apply-function-value-repeatedly", |b| {
+        b.iter_batched(
+            || {
+                let mut egraph = EGraph::default();
+                egraph.parse_and_run_program(None, setup).unwrap();
+                egraph
+            },
+            |mut egraph| egraph.parse_and_run_program(None, apply).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// Building a long string one piece at a time, with a `StringBuilder` and with repeated
+/// concatenation, which interns every intermediate string.
+pub fn build_strings(c: &mut Criterion) {
+    let n = 2_000;
+    let concatenate = format!(
+        "(function concatenated (i64) String :no-merge)
+        (set (concatenated 0) \"\")
+        (rule ((= s (concatenated i)) (< i {n}))
+              ((set (concatenated (+ i 1)) (+ s (to-string i) \",\"))))
+        (run {n})"
+    );
+    let build = format!(
+        "(function built (i64) StringBuilder :no-merge)
+        (set (built 0) (sb-new))
+        (rule ((= sb (built i)) (< i {n}))
+              ((set (built (+ i 1)) (sb-push (sb-push sb (to-string i)) \",\"))))
+        (run {n})
+        (let result (sb-build (built {n})))"
+    );
+    c.bench_function("concatenate-strings", |b| {
+        b.iter(|| run_example("concatenate-strings", &concatenate, true))
+    });
+    c.bench_function("build-strings", |b| {
+        b.iter(|| run_example("build-strings", &build, true))
+    });
+}
+
 criterion_group!(benches, criterion_benchmark);
-criterion_main!(benches);
+criterion_group!(
+    apply_benches,
+    apply_primitive_function_values,
+    apply_function_value_repeatedly
+);
+criterion_group!(
+    rebuild_benches,
+    rebuild_function_values,
+    rebuild_primitive_function_values
+);
+criterion_group!(string_benches, build_strings);
+criterion_main!(benches, rebuild_benches, apply_benches, string_benches);
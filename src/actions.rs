@@ -276,7 +276,7 @@ impl EGraph {
                             Value::unit()
                         } else if function.decl.subtype == FunctionSubtype::Constructor {
                             let value = Value {
-                                #[cfg(debug_assertions)]
+                                #[cfg(any(debug_assertions, feature = "value-tags"))]
                                 tag: function.schema.output.name(),
                                 bits: self.unionfind.make_set(),
                             };
@@ -401,7 +401,7 @@ impl EGraph {
                                 .nodes
                                 .insert_and_merge(args, self.timestamp, true, |old| {
                                     old.unwrap_or_else(|| Value {
-                                        #[cfg(debug_assertions)]
+                                        #[cfg(any(debug_assertions, feature = "value-tags"))]
                                         tag: function.schema.output.name(),
                                         bits: self.unionfind.make_set(),
                                     })
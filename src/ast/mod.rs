@@ -227,6 +227,10 @@ pub enum GenericSchedule<Head, Leaf> {
     Repeat(Span, usize, Box<GenericSchedule<Head, Leaf>>),
     Run(Span, GenericRunConfig<Head, Leaf>),
     Sequence(Span, Vec<GenericSchedule<Head, Leaf>>),
+    /// `(adaptive r1 r2 ...)` runs the rulesets in rounds until none of them changes
+    /// the database. A ruleset whose run adds no new facts is skipped for exponentially
+    /// more rounds each time, until a run adds new facts again.
+    Adaptive(Span, Vec<Symbol>),
 }
 
 impl<Head, Leaf> GenericSchedule<Head, Leaf>
@@ -250,6 +254,7 @@ where
                 span,
                 scheds.into_iter().map(|s| s.visit_exprs(f)).collect(),
             ),
+            GenericSchedule::Adaptive(span, rulesets) => GenericSchedule::Adaptive(span, rulesets),
         }
     }
 }
@@ -263,6 +268,9 @@ impl<Head: Display, Leaf: Display> Display for GenericSchedule<Head, Leaf> {
             GenericSchedule::Sequence(_ann, scheds) => {
                 write!(f, "(seq {})", ListDisplay(scheds, " "))
             }
+            GenericSchedule::Adaptive(_ann, rulesets) => {
+                write!(f, "(adaptive {})", ListDisplay(rulesets, " "))
+            }
         }
     }
 }
@@ -292,6 +300,8 @@ where
     /// Options supported include:
     /// - "interactive_mode" (default: false): when enabled, egglog prints "(done)" after each command, allowing an external
     /// tool to know when each command has finished running.
+    /// - "strict_fn_names" (default: false): when enabled, `unstable-fn` with a literal name that is
+    /// neither a function nor a primitive is a type error, instead of creating a value that fails when applied.
     SetOption {
         name: Symbol,
         value: GenericExpr<Head, Leaf>,
@@ -323,7 +323,7 @@ impl Parser {
                     vec![Command::Sort(
                         span,
                         name.expect_atom("sort name")?,
-                        Some((func, map_fallible(args, self, Self::parse_expr)?)),
+                        Some((func, map_fallible(args, self, Self::parse_sort_arg)?)),
                     )]
                 }
                 _ => {
@@ -688,6 +688,12 @@ impl Parser {
                 ),
                 _ => return error!(span, "usage: (repeat <number of iterations> <schedule>*)"),
             },
+            "adaptive" => Schedule::Adaptive(
+                span,
+                tail.iter()
+                    .map(|ruleset| ruleset.expect_atom("ruleset name"))
+                    .collect::<Result<_, _>>()?,
+            ),
             "run" => {
                 let has_ruleset = match tail.first() {
                     None => false,
@@ -709,7 +715,12 @@ impl Parser {
 
                 Schedule::Run(span, RunConfig { ruleset, until })
             }
-            _ => return error!(span, "expected either saturate, seq, repeat, or run"),
+            _ => {
+                return error!(
+                    span,
+                    "expected either saturate, seq, repeat, adaptive, or run"
+                )
+            }
         })
     }
 
@@ -818,6 +829,28 @@ impl Parser {
         })
     }
 
+    /// Parse an argument of a container sort declaration. Unlike an expression, a list may
+    /// start with another list, like the inputs `((Vec i64) String)` of a function sort.
+    /// Such a list is parsed as a call with an empty head.
+    fn parse_sort_arg(&mut self, sexp: &Sexp) -> Result<Expr, ParseError> {
+        Ok(match sexp {
+            Sexp::List(list, span) => match list.as_slice() {
+                [] => Expr::Lit(span.clone(), Literal::Unit),
+                [Sexp::List(..), ..] => Expr::Call(
+                    span.clone(),
+                    "".into(),
+                    map_fallible(list, self, Self::parse_sort_arg)?,
+                ),
+                [head, tail @ ..] => Expr::Call(
+                    span.clone(),
+                    head.expect_atom("sort")?,
+                    map_fallible(tail, self, Self::parse_sort_arg)?,
+                ),
+            },
+            _ => self.parse_expr(sexp)?,
+        })
+    }
+
     fn rec_datatype(&mut self, sexp: &Sexp) -> Result<(Span, Symbol, Subdatatypes), ParseError> {
         let (head, tail, span) = sexp.expect_call("datatype")?;
 
@@ -826,7 +859,7 @@ impl Parser {
                 [name, call] => {
                     let name = name.expect_atom("sort name")?;
                     let (func, args, _) = call.expect_call("container sort declaration")?;
-                    let args = map_fallible(args, self, Self::parse_expr)?;
+                    let args = map_fallible(args, self, Self::parse_sort_arg)?;
                     (span, name, Subdatatypes::NewSort(func, args))
                 }
                 _ => {
@@ -10,6 +10,12 @@ use std::{fmt::Debug, iter::once, mem::swap};
 
 #[derive(Clone, Debug)]
 pub enum ImpossibleConstraint {
+    // A literal width argument that does not match the width of the sort
+    WidthMismatch {
+        sort: ArcSort,
+        width: i64,
+        span: Span,
+    },
     ArityMismatch {
         atom: Atom<Symbol>,
         // The expected arity for this atom
@@ -21,6 +27,16 @@ pub enum ImpossibleConstraint {
         actual_output: ArcSort,
         actual_input: Vec<ArcSort>,
     },
+    // A literal function name that is neither a function nor a primitive
+    UnknownFunction {
+        name: Symbol,
+        span: Span,
+    },
+    // A literal function name that should be a constructor, but is something else
+    NotConstructor {
+        name: Symbol,
+        span: Span,
+    },
 }
 
 pub trait Constraint<Var, Value> {
@@ -294,6 +310,11 @@ impl ConstraintError<AtomTerm, ArcSort> {
             ConstraintError::NoConstraintSatisfied(constraints) => TypeError::AllAlternativeFailed(
                 constraints.iter().map(|c| c.to_type_error()).collect(),
             ),
+            ConstraintError::ImpossibleCaseIdentified(ImpossibleConstraint::WidthMismatch {
+                sort,
+                width,
+                span,
+            }) => TypeError::WidthMismatch(sort.clone(), *width, span.clone()),
             ConstraintError::ImpossibleCaseIdentified(ImpossibleConstraint::ArityMismatch {
                 atom,
                 expected,
@@ -312,6 +333,14 @@ impl ConstraintError<AtomTerm, ArcSort> {
                 actual_output.clone(),
                 actual_input.clone(),
             ),
+            ConstraintError::ImpossibleCaseIdentified(ImpossibleConstraint::UnknownFunction {
+                name,
+                span,
+            }) => TypeError::UnboundFunction(*name, span.clone()),
+            ConstraintError::ImpossibleCaseIdentified(ImpossibleConstraint::NotConstructor {
+                name,
+                span,
+            }) => TypeError::NotConstructor(*name, span.clone()),
         }
     }
 }
@@ -1,10 +1,31 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
 use crate::ast::Symbol;
+use crate::sort::{FromSort, FunctionSort, I64Sort, IntoSort, PureCall, Sort, StringSort, VecSort};
 use crate::termdag::{Term, TermDag};
-use crate::util::HashMap;
+use crate::util::{HashMap, HashSet};
 use crate::{ArcSort, EGraph, Error, Function, HEntry, Id, Value};
 
 pub type Cost = usize;
 
+/// Why a container value could not be extracted, see [`FunctionSort::try_extract_term`].
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ExtractionError {
+    #[error("{1:?} is not a value of sort {0}")]
+    InvalidValue(Symbol, Value),
+    /// The partial arg at `index` of a function value wrapping `function` has no term,
+    /// e.g. because its e-class has no extractable representative.
+    #[error(
+        "Partial arg {index} of sort {sort} of a function value of {function} can't be extracted"
+    )]
+    PartialArg {
+        function: Symbol,
+        index: usize,
+        sort: Symbol,
+    },
+}
+
 #[derive(Debug)]
 pub(crate) struct Node<'a> {
     sym: Symbol,
@@ -16,6 +37,67 @@ pub struct Extractor<'a> {
     pub costs: HashMap<Id, (Cost, Term)>,
     ctors: Vec<Symbol>,
     egraph: &'a EGraph,
+    cost_fn: Option<CostFn>,
+    /// Whether to count shared subterms once, see [`Extractor::new_dag`].
+    dag: bool,
+    /// Whether to render curried function values as the applications that return them,
+    /// see [`Extractor::new_nested_apps`].
+    nested_apps: bool,
+    /// Container values whose terms are being extracted, see [`Extractor::extract_acyclic`].
+    visiting: RefCell<Vec<(Symbol, Value)>>,
+}
+
+/// A cost model given as a function value, see [`EGraph::extract_with_cost_fn`].
+struct CostFn {
+    call: PureCall,
+    costs: Arc<VecSort>,
+}
+
+impl CostFn {
+    fn new(egraph: &EGraph, sort: &ArcSort, value: Value) -> Result<Self, Error> {
+        let fn_sort = sort
+            .clone()
+            .as_arc_any()
+            .downcast::<FunctionSort>()
+            .map_err(|_| Error::NotFunctionSort(sort.name()))?;
+        let costs = match &fn_sort.inputs[..] {
+            [name, costs]
+                if !fn_sort.variadic
+                    && name.name() == StringSort.name()
+                    && fn_sort.output.name() == I64Sort.name() =>
+            {
+                costs
+                    .clone()
+                    .as_arc_any()
+                    .downcast::<VecSort>()
+                    .ok()
+                    .filter(|costs| costs.element_name() == I64Sort.name())
+            }
+            _ => None,
+        }
+        .ok_or_else(|| {
+            Error::InvalidCostFn(
+                sort.name(),
+                "its sort must be (UnstableFn (String (Vec i64)) i64)".into(),
+            )
+        })?;
+        let call = fn_sort
+            .pure_call(&value, &egraph.type_info)
+            .ok_or_else(|| Error::InvalidCostFn(sort.name(), "it must wrap a primitive".into()))?;
+        Ok(CostFn { call, costs })
+    }
+
+    /// The cost of a node with the given children's costs, or `None` if the cost
+    /// function fails or returns a negative cost.
+    fn cost(&self, sym: Symbol, child_costs: &[Cost]) -> Option<Cost> {
+        let child_costs: Vec<Value> = child_costs
+            .iter()
+            .map(|cost| i64::try_from(*cost).unwrap_or(i64::MAX).store(&I64Sort))
+            .collect::<Option<_>>()?;
+        let args = [sym.store(&StringSort)?, child_costs.store(&self.costs)?];
+        let cost = self.call.call(&args)?;
+        Cost::try_from(i64::load(&I64Sort, &cost)).ok()
+    }
 }
 
 impl EGraph {
@@ -44,6 +126,54 @@ impl EGraph {
         arcsort: &ArcSort,
     ) -> Result<(Cost, Term), Error> {
         let extractor = Extractor::new(self, termdag);
+        self.extract_from(&extractor, value, termdag, arcsort)
+    }
+
+    /// Like [`EGraph::extract`], but with the cost of each node given by the function
+    /// value `cost_fn` of the function sort `cost_fn_sort`, instead of the size of the term.
+    ///
+    /// The cost function is called with the name of the node's function and the costs of
+    /// its children, and returns the cost of the whole node, so it should usually add
+    /// the children's costs in some way. Nodes for which it fails or returns a negative
+    /// cost are never extracted. Children that are not e-classes, like literals, cost
+    /// as much as they do by default.
+    ///
+    /// Extraction does not change the e-graph, so `cost_fn` must wrap a primitive
+    /// (possibly with partial args) rather than a declared function. It is called like
+    /// in a query, so primitives that need the e-graph, like `unstable-app`, can't be used.
+    pub fn extract_with_cost_fn(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+        cost_fn_sort: &ArcSort,
+        cost_fn: Value,
+    ) -> Result<(Cost, Term), Error> {
+        let cost_fn = CostFn::new(self, cost_fn_sort, cost_fn)?;
+        let extractor = Extractor::with_cost_fn(self, termdag, Some(cost_fn));
+        self.extract_from(&extractor, value, termdag, arcsort)
+    }
+
+    /// Like [`EGraph::extract`], but with the cost of a term being the cost of its DAG,
+    /// where subterms that occur several times are only counted once.
+    /// See [`Extractor::new_dag`].
+    pub fn extract_dag(
+        &self,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+    ) -> Result<(Cost, Term), Error> {
+        let extractor = Extractor::new_dag(self, termdag);
+        self.extract_from(&extractor, value, termdag, arcsort)
+    }
+
+    fn extract_from(
+        &self,
+        extractor: &Extractor,
+        value: Value,
+        termdag: &mut TermDag,
+        arcsort: &ArcSort,
+    ) -> Result<(Cost, Term), Error> {
         extractor.find_best(value, termdag, arcsort).ok_or_else(|| {
             log::error!("No cost for {:?}", value);
             for func in self.functions.values() {
@@ -109,10 +239,60 @@ impl EGraph {
 
 impl<'a> Extractor<'a> {
     pub fn new(egraph: &'a EGraph, termdag: &mut TermDag) -> Self {
+        Self::with_cost_fn(egraph, termdag, None)
+    }
+
+    /// An extractor that minimizes the size of terms as DAGs instead of as trees.
+    ///
+    /// Each distinct subterm is counted once, costing the `:cost` of its function, or 1
+    /// if it has none or is not a function call (like a literal). Finding the smallest
+    /// DAG is hard in general, so the best terms are still built greedily from the best
+    /// terms of their children, but shared children no longer make a term look bigger.
+    pub fn new_dag(egraph: &'a EGraph, termdag: &mut TermDag) -> Self {
+        Self::with_costs(egraph, termdag, None, true, false)
+    }
+
+    /// An extractor like [`Extractor::new`], except that a curried function value
+    /// `(unstable-fn "unstable-curried" f a b)` is rendered as the applications that
+    /// return it, `(unstable-app (unstable-app (unstable-fn "unstable-curried" f) a) b)`.
+    /// Both terms evaluate to the same value, and cost the same.
+    pub fn new_nested_apps(egraph: &'a EGraph, termdag: &mut TermDag) -> Self {
+        Self::with_costs(egraph, termdag, None, false, true)
+    }
+
+    /// An extractor that knows no e-class costs, enough for sorts whose values
+    /// are extracted without looking into the e-graph.
+    pub(crate) fn without_costs(egraph: &'a EGraph) -> Self {
+        Extractor {
+            costs: HashMap::default(),
+            egraph,
+            ctors: vec![],
+            cost_fn: None,
+            dag: false,
+            nested_apps: false,
+            visiting: Default::default(),
+        }
+    }
+
+    fn with_cost_fn(egraph: &'a EGraph, termdag: &mut TermDag, cost_fn: Option<CostFn>) -> Self {
+        Self::with_costs(egraph, termdag, cost_fn, false, false)
+    }
+
+    fn with_costs(
+        egraph: &'a EGraph,
+        termdag: &mut TermDag,
+        cost_fn: Option<CostFn>,
+        dag: bool,
+        nested_apps: bool,
+    ) -> Self {
         let mut extractor = Extractor {
             costs: HashMap::default(),
             egraph,
             ctors: vec![],
+            cost_fn,
+            dag,
+            nested_apps,
+            visiting: Default::default(),
         };
 
         // only consider "extractable" functions
@@ -159,6 +339,72 @@ impl<'a> Extractor<'a> {
         }
     }
 
+    /// Whether curried function values are rendered as nested applications, see
+    /// [`Extractor::new_nested_apps`].
+    pub fn nested_apps(&self) -> bool {
+        self.nested_apps
+    }
+
+    /// The cost of `term`, a node that costs `cost` by itself, given the costs of its
+    /// children. This is their sum, unless the extractor counts costs as a DAG (see
+    /// [`Extractor::new_dag`]), so sorts should use it to cost the terms they extract.
+    pub fn node_cost(
+        &self,
+        term: &Term,
+        termdag: &TermDag,
+        cost: Cost,
+        child_costs: &[Cost],
+    ) -> Cost {
+        if self.dag {
+            self.dag_cost(term, termdag)
+        } else {
+            child_costs
+                .iter()
+                .fold(cost, |cost, child| cost.saturating_add(*child))
+        }
+    }
+
+    /// The sum of the costs of the distinct subterms of `term`, including itself.
+    fn dag_cost(&self, term: &Term, termdag: &TermDag) -> Cost {
+        let mut seen = HashSet::default();
+        let mut stack = vec![termdag.lookup(term)];
+        let mut cost: Cost = 0;
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let node = termdag.get(id);
+            let node_cost = match node {
+                Term::App(sym, children) => {
+                    stack.extend(children);
+                    let function = self.egraph.functions.get(sym);
+                    function.and_then(|f| f.decl.cost).unwrap_or(1)
+                }
+                Term::Lit(_) | Term::Var(_) => 1,
+            };
+            cost = cost.saturating_add(node_cost);
+        }
+        cost
+    }
+
+    /// Run `extract` for a container `value` of sort `sort`, unless that value is
+    /// already being extracted further up, i.e. it contains itself. Such a value has
+    /// no finite term, so this returns `None` instead of recursing forever.
+    pub fn extract_acyclic(
+        &self,
+        sort: Symbol,
+        value: Value,
+        extract: impl FnOnce() -> Option<(Cost, Term)>,
+    ) -> Option<(Cost, Term)> {
+        if self.visiting.borrow().contains(&(sort, value)) {
+            return None;
+        }
+        self.visiting.borrow_mut().push((sort, value));
+        let result = extract();
+        self.visiting.borrow_mut().pop();
+        result
+    }
+
     fn node_total_cost(
         &mut self,
         function: &Function,
@@ -168,10 +414,18 @@ impl<'a> Extractor<'a> {
         let mut cost = function.decl.cost.unwrap_or(1);
         let types = &function.schema.input;
         let mut terms: Vec<Term> = vec![];
+        let mut child_costs = vec![];
         for (ty, value) in types.iter().zip(children) {
             let (term_cost, term) = self.find_best(*value, termdag, ty)?;
             terms.push(term.clone());
             cost = cost.saturating_add(term_cost);
+            child_costs.push(term_cost);
+        }
+        if let Some(cost_fn) = &self.cost_fn {
+            cost = cost_fn.cost(function.decl.name, &child_costs)?;
+        } else if self.dag {
+            let term = termdag.app(function.decl.name, terms.clone());
+            cost = self.dag_cost(&term, termdag);
         }
         Some((terms, cost))
     }
@@ -55,7 +55,7 @@ mod tests {
 
     fn make_value(bits: u32) -> Value {
         Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: "testing".into(),
             bits: bits as u64,
         }
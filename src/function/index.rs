@@ -50,7 +50,7 @@ impl ColumnIndex {
         self.ids.iter().map(|(bits, v)| {
             (
                 Value {
-                    #[cfg(debug_assertions)]
+                    #[cfg(any(debug_assertions, feature = "value-tags"))]
                     tag: self.sort,
                     bits: *bits,
                 },
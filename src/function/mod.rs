@@ -24,6 +24,41 @@ pub struct Function {
     scratch: IndexSet<usize>,
 }
 
+/// Rows canonicalized by [`Function::canonicalize_rows`], stored by column.
+struct CanonicalRows {
+    offsets: Vec<usize>,
+    /// Empty if the rows were not canonicalized ahead of time.
+    original_outputs: Vec<Value>,
+    /// The canonical inputs, followed by the canonical output.
+    columns: Vec<Vec<Value>>,
+    /// The number of unions when the rows were canonicalized. Once there are more, the
+    /// rows may no longer be canonical.
+    n_unions: usize,
+}
+
+/// One row of [`CanonicalRows`].
+struct CanonicalRow {
+    original_output: Value,
+    inputs: ValueVec,
+    output: Value,
+}
+
+impl CanonicalRows {
+    fn iter(&self) -> impl Iterator<Item = (usize, Option<CanonicalRow>)> + '_ {
+        self.offsets.iter().enumerate().map(move |(r, &i)| {
+            let row = self
+                .columns
+                .split_last()
+                .map(|(output, inputs)| CanonicalRow {
+                    original_output: self.original_outputs[r],
+                    inputs: inputs.iter().map(|column| column[r]).collect(),
+                    output: output[r],
+                });
+            (i, row)
+        })
+    }
+}
+
 #[derive(Clone)]
 pub enum MergeFn {
     AssertEq,
@@ -341,8 +376,10 @@ impl Function {
         if uf.new_ids(|sort| self.sorts.contains(&sort)) > (self.nodes.num_offsets() / 2) {
             // basic heuristic: if we displaced a large number of ids relative
             // to the size of the table, then just rebuild everything.
-            for i in 0..self.nodes.num_offsets() {
-                self.rebuild_at(i, timestamp, uf, &mut scratch, &mut deferred_merges)?;
+            let batch = self.canonicalize_rows(0..self.nodes.num_offsets(), uf);
+            for (i, row) in batch.iter() {
+                let row = row.filter(|_| uf.n_unions() == batch.n_unions);
+                self.rebuild_at(i, timestamp, uf, &mut scratch, &mut deferred_merges, row)?;
             }
         } else {
             let mut to_canon = mem::take(&mut self.scratch);
@@ -371,8 +408,10 @@ impl Function {
                 }
             }
 
-            for i in to_canon.iter().copied() {
-                self.rebuild_at(i, timestamp, uf, &mut scratch, &mut deferred_merges)?;
+            let batch = self.canonicalize_rows(to_canon.iter().copied(), uf);
+            for (i, row) in batch.iter() {
+                let row = row.filter(|_| uf.n_unions() == batch.n_unions);
+                self.rebuild_at(i, timestamp, uf, &mut scratch, &mut deferred_merges, row)?;
             }
             self.scratch = to_canon;
         }
@@ -383,6 +422,60 @@ impl Function {
         ))
     }
 
+    /// Canonicalize the given rows ahead of rebuilding them, a column at a time, so that
+    /// sorts can batch the work with [`Sort::canonicalize_batch`].
+    ///
+    /// Only tables with container columns benefit, so for other tables this leaves the
+    /// rows to be canonicalized one at a time while rebuilding.
+    fn canonicalize_rows(
+        &self,
+        rows: impl Iterator<Item = usize>,
+        uf: &UnionFind,
+    ) -> CanonicalRows {
+        let width = self.schema.input.len() + 1;
+        let batched = self
+            .schema
+            .input
+            .iter()
+            .chain(once(&self.schema.output))
+            .any(|sort| sort.is_eq_container_sort());
+        if !batched {
+            return CanonicalRows {
+                offsets: rows.collect(),
+                original_outputs: vec![],
+                columns: vec![],
+                n_unions: uf.n_unions(),
+            };
+        }
+        let mut offsets = vec![];
+        let mut columns = vec![vec![]; width];
+        for i in rows {
+            // Stale rows are skipped when rebuilding
+            let Some((args, out)) = self.nodes.get_index(i, true) else {
+                continue;
+            };
+            offsets.push(i);
+            for (column, value) in columns.iter_mut().zip(args.iter().chain(once(&out.value))) {
+                column.push(*value);
+            }
+        }
+        let original_outputs = columns[width - 1].clone();
+        for (column, sort) in columns
+            .iter_mut()
+            .zip(self.schema.input.iter().chain(once(&self.schema.output)))
+        {
+            sort.canonicalize_batch(column, uf);
+        }
+        CanonicalRows {
+            offsets,
+            original_outputs,
+            columns,
+            n_unions: uf.n_unions(),
+        }
+    }
+
+    /// Rebuild the row at offset `i`, using `canonical` for its canonical values if it
+    /// was canonicalized ahead of time with the current union-find.
     fn rebuild_at(
         &mut self,
         i: usize,
@@ -390,6 +483,7 @@ impl Function {
         uf: &mut UnionFind,
         scratch: &mut ValueVec,
         deferred_merges: &mut Vec<(ValueVec, Value, Value)>,
+        canonical: Option<CanonicalRow>,
     ) -> Result<(), Error> {
         let mut result: Result<(), Error> = Ok(());
         let mut modified = false;
@@ -402,14 +496,23 @@ impl Function {
 
         let mut out_val = out.value;
         scratch.clear();
-        scratch.extend(args.iter().copied());
-
-        for (val, ty) in scratch.iter_mut().zip(&self.schema.input) {
-            modified |= ty.canonicalize(val, uf);
+        // Rebuilding earlier rows may have merged into this one's output
+        match canonical.filter(|row| row.original_output == out.value) {
+            Some(row) => {
+                scratch.extend(row.inputs);
+                out_val = row.output;
+                // Canonicalizing changes a value exactly when it reports a change
+                modified = &scratch[..] != args || out_val != out.value;
+            }
+            None => {
+                scratch.extend(args.iter().copied());
+                for (val, ty) in scratch.iter_mut().zip(&self.schema.input) {
+                    modified |= ty.canonicalize(val, uf);
+                }
+                modified |= self.schema.output.canonicalize(&mut out_val, uf);
+            }
         }
 
-        modified |= self.schema.output.canonicalize(&mut out_val, uf);
-
         if !modified {
             return result;
         }
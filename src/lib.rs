@@ -20,6 +20,7 @@ pub mod extract;
 mod function;
 mod gj;
 mod serialize;
+mod snapshot;
 pub mod sort;
 mod termdag;
 mod typechecking;
@@ -29,7 +30,6 @@ mod value;
 
 use crate::constraint::Problem;
 use crate::core::{AtomTerm, ResolvedCall};
-use crate::typechecking::TypeError;
 use actions::Program;
 use ast::remove_globals::remove_globals;
 use ast::*;
@@ -44,6 +44,7 @@ use index::ColumnIndex;
 use indexmap::map::Entry;
 use instant::{Duration, Instant};
 pub use serialize::{SerializeConfig, SerializedNode};
+pub use snapshot::{EGraphDiff, Snapshot};
 use sort::*;
 use std::fmt::Debug;
 use std::fmt::{Display, Formatter};
@@ -57,7 +58,7 @@ use std::str::FromStr;
 use std::sync::Arc;
 pub use termdag::{Term, TermDag, TermId};
 use thiserror::Error;
-pub use typechecking::TypeInfo;
+pub use typechecking::{TypeError, TypeInfo};
 use unionfind::*;
 use util::*;
 pub use value::*;
@@ -94,6 +95,7 @@ pub struct RunReport {
     pub apply_time_per_rule: HashMap<Symbol, Duration>,
     pub search_time_per_ruleset: HashMap<Symbol, Duration>,
     pub num_matches_per_rule: HashMap<Symbol, usize>,
+    pub num_matches_per_ruleset: HashMap<Symbol, usize>,
     pub apply_time_per_ruleset: HashMap<Symbol, Duration>,
     pub rebuild_time_per_ruleset: HashMap<Symbol, Duration>,
 }
@@ -135,6 +137,10 @@ impl RunReport {
     fn add_rule_num_matches(&mut self, rule: Symbol, num_matches: usize) {
         *self.num_matches_per_rule.entry(rule).or_default() += num_matches;
     }
+
+    fn add_ruleset_num_matches(&mut self, ruleset: Symbol, num_matches: usize) {
+        *self.num_matches_per_ruleset.entry(ruleset).or_default() += num_matches;
+    }
 }
 
 impl Display for RunReport {
@@ -211,9 +217,14 @@ impl Display for RunReport {
                 .cloned()
                 .unwrap_or(Duration::default())
                 .as_secs_f64();
+            let num_matches = self
+                .num_matches_per_ruleset
+                .get(ruleset)
+                .cloned()
+                .unwrap_or(0);
             writeln!(
                 f,
-                "Ruleset {ruleset}: search {search_time:.3}s, apply {apply_time:.3}s, rebuild {rebuild_time:.3}s",
+                "Ruleset {ruleset}: search {search_time:.3}s, apply {apply_time:.3}s, rebuild {rebuild_time:.3}s, num matches {num_matches}",
             )?;
         }
 
@@ -275,6 +286,10 @@ impl RunReport {
                 &self.num_matches_per_rule,
                 &other.num_matches_per_rule,
             ),
+            num_matches_per_ruleset: Self::union_counts(
+                &self.num_matches_per_ruleset,
+                &other.num_matches_per_ruleset,
+            ),
             search_time_per_ruleset: Self::union_times(
                 &self.search_time_per_ruleset,
                 &other.search_time_per_ruleset,
@@ -298,8 +313,10 @@ impl Primitive {
     // Returns whether the primitive is compatible with this signature
     fn accept(&self, tys: &[Arc<dyn Sort>], typeinfo: &TypeInfo) -> bool {
         let mut constraints = vec![];
+        // Use fresh variables rather than literals, so that constraints which
+        // inspect literal arguments (e.g. a literal function name) are not fooled
         let lits: Vec<_> = (0..tys.len())
-            .map(|i| AtomTerm::Literal(Span::Panic, Literal::Int(i as i64)))
+            .map(|i| AtomTerm::Var(Span::Panic, format!("__arg_{i}").into()))
             .collect();
         for (lit, ty) in lits.iter().zip(tys.iter()) {
             constraints.push(constraint::assign(lit.clone(), ty.clone()))
@@ -426,6 +443,43 @@ impl FromStr for RunMode {
     }
 }
 
+/// Tracks how often a ruleset in an `adaptive` schedule should run.
+/// Each time a run of the ruleset adds no new facts, it is skipped for twice as
+/// many rounds as the last time, until a run adds new facts again. A ruleset
+/// whose matches only re-derive existing facts is backed off like one with no
+/// matches at all.
+#[derive(Clone, Default)]
+struct Backoff {
+    skip: usize,
+    backoff: usize,
+}
+
+impl Backoff {
+    fn should_run(&mut self) -> bool {
+        if self.skip > 0 {
+            self.skip -= 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    fn record(&mut self, updated: bool) {
+        if updated {
+            self.backoff = 0;
+        } else {
+            self.backoff = (self.backoff * 2).max(1);
+            self.skip = self.backoff;
+        }
+    }
+
+    /// Lets the ruleset run in the next round, keeping its backoff for when
+    /// that run adds no new facts either.
+    fn reset(&mut self) {
+        self.skip = 0;
+    }
+}
+
 #[derive(Clone)]
 pub struct EGraph {
     pub parser: Parser,
@@ -447,6 +501,17 @@ pub struct EGraph {
     overall_run_report: RunReport,
     /// Messages to be printed to the user. If this is `None`, then we are ignoring messages.
     msgs: Option<Vec<String>>,
+    /// Programs compiled for applying function values, keyed by the function name
+    /// and the names of its argument and output sorts.
+    fn_programs: HashMap<(Symbol, Vec<Symbol>), CompiledCall>,
+    /// Results of `unstable-app-memo`, keyed by the function sort, the function value's
+    /// name and partial args, and the args it was applied to.
+    fn_memo: HashMap<(Symbol, Symbol, Vec<Value>, Vec<Value>), Value>,
+    /// The number of unions when `fn_memo` was last cleared.
+    fn_memo_unions: usize,
+    /// For a pushed e-graph, the sizes of the sorts' interning tables when it was pushed.
+    /// The sorts are shared with the e-graph pushed on top, so `pop` truncates them back.
+    interned_at_push: Vec<(ArcSort, usize)>,
 }
 
 impl Default for EGraph {
@@ -466,6 +531,10 @@ impl Default for EGraph {
             extract_report: None,
             recent_run_report: None,
             overall_run_report: Default::default(),
+            fn_programs: Default::default(),
+            fn_memo: Default::default(),
+            fn_memo_unions: 0,
+            interned_at_push: vec![],
             msgs: Some(vec![]),
             type_info: Default::default(),
         };
@@ -495,7 +564,16 @@ impl EGraph {
     }
 
     pub fn push(&mut self) {
-        self.egraphs.push(self.clone());
+        let mut pushed = self.clone();
+        // Skip aliases, which share the table of the sort they alias
+        pushed.interned_at_push = self
+            .type_info
+            .sorts
+            .iter()
+            .filter(|(name, sort)| **name == sort.name())
+            .map(|(_, sort)| (sort.clone(), sort.snapshot_len()))
+            .collect();
+        self.egraphs.push(pushed);
     }
 
     /// Disable saving messages to be printed to the user and remove any saved messages.
@@ -529,6 +607,12 @@ impl EGraph {
                 let messages = self.msgs.clone();
 
                 *self = e;
+                // Values interned since the push were only referenced from the popped
+                // e-graph. Ones interned before keep their indices, even if they were
+                // stored again, e.g. when canonicalizing.
+                for (sort, len) in std::mem::take(&mut self.interned_at_push) {
+                    sort.truncate_to(len);
+                }
                 self.extract_report = extract_report.or(self.extract_report.clone());
                 // We union the run reports, meaning
                 // that statistics are shared across
@@ -607,7 +691,7 @@ impl EGraph {
     pub fn find(&self, sort: &ArcSort, value: Value) -> Value {
         if sort.is_eq_sort() {
             Value {
-                #[cfg(debug_assertions)]
+                #[cfg(any(debug_assertions, feature = "value-tags"))]
                 tag: value.tag,
                 bits: self.unionfind.find(value.bits),
             }
@@ -638,6 +722,12 @@ impl EGraph {
                 break;
             }
         }
+        // Memoized results may no longer be canonical, or rows they were computed from
+        // may have been merged
+        if updates > 0 || self.unionfind.n_unions() != self.fn_memo_unions {
+            self.fn_memo.clear();
+            self.fn_memo_unions = self.unionfind.n_unions();
+        }
 
         self.debug_assert_invariants();
         Ok(updates)
@@ -862,6 +952,37 @@ impl EGraph {
                 }
                 report
             }
+            ResolvedSchedule::Adaptive(span, rulesets) => {
+                let mut report = RunReport::default();
+                let mut backoffs = vec![Backoff::default(); rulesets.len()];
+                loop {
+                    let mut updated = false;
+                    let mut skipped = false;
+                    for (ruleset, backoff) in rulesets.iter().zip(&mut backoffs) {
+                        if !backoff.should_run() {
+                            skipped = true;
+                            continue;
+                        }
+                        let config = ResolvedRunConfig {
+                            ruleset: *ruleset,
+                            until: None,
+                        };
+                        let rec = self.run_rules(span, &config);
+                        backoff.record(rec.updated);
+                        updated |= rec.updated;
+                        report = report.union(&rec);
+                    }
+                    if !updated {
+                        if !skipped {
+                            break;
+                        }
+                        // Nothing changed, so give the skipped rulesets a chance
+                        // before deciding that we are done
+                        backoffs.iter_mut().for_each(Backoff::reset);
+                    }
+                }
+                report
+            }
         }
     }
 
@@ -881,6 +1002,57 @@ impl EGraph {
         Ok(termdag.to_string(&term))
     }
 
+    /// Extract every value of the sort named `sort` that appears in some function table,
+    /// along with the id of its best term in one shared [`TermDag`].
+    ///
+    /// Values are canonicalized and listed once each, in the order they are first found.
+    /// Since all terms go into the same `TermDag`, subterms common to several values,
+    /// including the captured arguments of function values, are only stored once.
+    /// Values with no extractable term are left out.
+    pub fn extract_all_of_sort(
+        &self,
+        sort: Symbol,
+    ) -> Result<(TermDag, Vec<(Value, TermId)>), Error> {
+        let arcsort = self
+            .type_info
+            .sorts
+            .get(&sort)
+            .ok_or(TypeError::UndefinedSort(sort, span!()))?
+            .clone();
+
+        let mut values = IndexSet::default();
+        for function in self.functions.values() {
+            let schema = &function.schema;
+            for (inputs, output) in function.nodes.iter(false) {
+                let row = inputs.iter().zip(&schema.input);
+                for (value, value_sort) in row.chain([(&output.value, &schema.output)]) {
+                    if value_sort.name() == sort {
+                        values.insert(self.find(&arcsort, *value));
+                    }
+                }
+            }
+        }
+
+        let mut termdag = TermDag::default();
+        let extractor = Extractor::new(self, &mut termdag);
+        let terms = values
+            .into_iter()
+            .filter_map(|value| {
+                let (_, term) = extractor.find_best(value, &mut termdag, &arcsort)?;
+                Some((value, termdag.lookup(&term)))
+            })
+            .collect();
+        drop(extractor);
+
+        Ok((termdag, terms))
+    }
+
+    /// Render a value for debugging, see [`Sort::format_value`].
+    /// Unlike `extract_value_to_string` this does not extract terms for e-classes.
+    pub fn display_value(&self, sort: &ArcSort, value: Value) -> String {
+        sort.format_value(&value, self)
+    }
+
     fn run_rules(&mut self, span: &Span, config: &ResolvedRunConfig) -> RunReport {
         let mut report: RunReport = Default::default();
 
@@ -972,14 +1144,16 @@ impl EGraph {
         }
     }
 
+    /// Apply the search results for a ruleset, returning the total number of matches.
     fn apply_rules(
         &mut self,
         ruleset: Symbol,
         run_report: &mut RunReport,
         search_results: &HashMap<Symbol, SearchResult>,
-    ) {
+    ) -> usize {
         // TODO this clone is not efficient
         let rules = self.rulesets.get(&ruleset).unwrap().clone();
+        let mut num_matches = 0;
         match rules {
             Ruleset::Rules(_name, compiled_rules) => {
                 let apply_start = Instant::now();
@@ -995,6 +1169,9 @@ impl EGraph {
                     // make sure the query requires matches
                     if num_vars != 0 {
                         run_report.add_rule_num_matches(rule_name, all_matches.len() / num_vars);
+                        num_matches += all_matches.len() / num_vars;
+                    } else if *did_match {
+                        num_matches += 1;
                     }
 
                     self.rule_last_run_timestamp
@@ -1031,12 +1208,14 @@ impl EGraph {
             Ruleset::Combined(_name, sub_rulesets) => {
                 let start_time = Instant::now();
                 for sub_ruleset in sub_rulesets {
-                    self.apply_rules(sub_ruleset, run_report, search_results);
+                    num_matches += self.apply_rules(sub_ruleset, run_report, search_results);
                 }
                 let apply_time = start_time.elapsed();
                 run_report.add_ruleset_apply_time(ruleset, apply_time);
             }
         }
+        run_report.add_ruleset_num_matches(ruleset, num_matches);
+        num_matches
     }
 
     fn step_rules(&mut self, ruleset: Symbol) -> RunReport {
@@ -1161,6 +1340,13 @@ impl EGraph {
                     panic!("interactive_mode must be an integer");
                 }
             }
+            "strict_fn_names" => {
+                if let ResolvedExpr::Lit(_ann, Literal::Int(i)) = value {
+                    self.type_info.strict_fn_names = i != 0;
+                } else {
+                    panic!("strict_fn_names must be an integer");
+                }
+            }
             _ => panic!("Unknown option '{}'", name),
         }
     }
@@ -1492,6 +1678,11 @@ impl EGraph {
         self.functions.values().map(|f| f.nodes.len()).sum()
     }
 
+    /// The sorts, primitives, and function signatures declared so far.
+    pub fn type_info(&self) -> &TypeInfo {
+        &self.type_info
+    }
+
     /// Returns a sort based on the type
     pub fn get_sort<S: Sort + Send + Sync>(&self) -> Option<Arc<S>> {
         self.type_info.get_sort_by(|_| true)
@@ -1506,11 +1697,69 @@ impl EGraph {
         self.type_info.get_sort_by(pred)
     }
 
+    /// The names of the sorts declared so far, including aliases and the built-in
+    /// sorts, sorted by name.
+    pub fn list_sorts(&self) -> Vec<Symbol> {
+        let mut names: Vec<Symbol> = self.type_info.sorts.keys().copied().collect();
+        names.sort_by_key(|name| name.as_str());
+        names
+    }
+
+    /// What the sort named `name` is, or `None` if there is no such sort.
+    pub fn sort_info(&self, name: Symbol) -> Option<SortInfo> {
+        self.type_info.sorts.get(&name).map(SortInfo::new)
+    }
+
+    /// The primitive names reserved by the registered presorts (such as `vec-of` or
+    /// `unstable-fn`), sorted by name. Names that no sort declared so far has
+    /// registered are included, since they still can't be used for functions.
+    pub fn reserved_primitives(&self) -> Vec<Symbol> {
+        let mut names: Vec<Symbol> = self.type_info.reserved_primitives.iter().copied().collect();
+        names.sort_by_key(|name| name.as_str());
+        names
+    }
+
+    /// Apply a function value `f` of the function sort `sort` to `args`.
+    ///
+    /// Fails if `sort` is not a function sort or if the number of arguments
+    /// does not match its inputs.
+    pub fn apply_function_value(
+        &mut self,
+        sort: &ArcSort,
+        f: Value,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let fn_sort = sort
+            .clone()
+            .as_arc_any()
+            .downcast::<FunctionSort>()
+            .map_err(|_| Error::NotFunctionSort(sort.name()))?;
+        fn_sort.apply(&f, args, self)
+    }
+
+    /// Set a callback to run whenever a new function value is interned, with the
+    /// function's name and the number of partial args, replacing any previous one.
+    /// Pass `None` to remove it.
+    ///
+    /// Values that were already interned, e.g. by applying the same partial args
+    /// again, do not trigger the callback. The callback is shared with e-graphs
+    /// pushed from or popped to this one, and must not create function values itself.
+    pub fn set_fn_intern_observer(&mut self, observer: Option<InternObserver>) {
+        self.type_info.fn_intern_observer.set(observer);
+    }
+
     /// Add a user-defined sort
     pub fn add_arcsort(&mut self, arcsort: ArcSort, span: Span) -> Result<(), TypeError> {
         self.type_info.add_arcsort(arcsort, span)
     }
 
+    /// Add a user-defined sort constructor, so that programs can declare sorts with it,
+    /// like `(sort IntVec (Vec i64))` for the built-in `Vec`. The names of its primitives
+    /// are reserved, so they can't be used for functions.
+    pub fn add_presort<P: Presort>(&mut self, span: Span) -> Result<(), TypeError> {
+        self.type_info.add_presort::<P>(span)
+    }
+
     /// Add a user-defined primitive
     pub fn add_primitive(&mut self, prim: impl Into<Primitive>) {
         self.type_info.add_primitive(prim)
@@ -1581,6 +1830,22 @@ pub enum Error {
     SubsumeMergeError(Symbol),
     #[error("extraction failure: {:?}", .0)]
     ExtractError(Value),
+    #[error("{0} is not a function sort")]
+    NotFunctionSort(Symbol),
+    #[error("Function value of sort {0} expects {1} arguments, got {2}")]
+    FunctionValueArity(Symbol, usize, usize),
+    #[error("Function value of sort {0} expects arg {1} to be of sort {2}, got {3}")]
+    FunctionValueArgSort(Symbol, usize, Symbol, Symbol),
+    #[error("{1:?} is not a value of sort {0}")]
+    InvalidValue(Symbol, Value),
+    #[error("Calling {0} through a function value produced no value")]
+    NoCallResult(Symbol),
+    #[error("Calling {0} through a function value with args {:?} failed: {2}", ListDebug(.1, " "))]
+    CallError(Symbol, Vec<Value>, Box<Error>),
+    #[error("{1} is not a term for a function value of sort {0}")]
+    InvalidFunctionTerm(Symbol, String),
+    #[error("Cannot use a value of {0} as a cost function: {1}")]
+    InvalidCostFn(Symbol, String),
 }
 
 #[cfg(test)]
@@ -1659,6 +1924,157 @@ mod tests {
             .unwrap();
     }
 
+    // (depth-cost name child-costs) is one more than the most costly child
+    struct DepthCost {
+        vec: Arc<VecSort>,
+    }
+
+    impl PrimitiveLike for DepthCost {
+        fn name(&self) -> symbol_table::GlobalSymbol {
+            "depth-cost".into()
+        }
+
+        fn get_type_constraints(&self, span: &Span) -> Box<dyn crate::constraint::TypeConstraint> {
+            SimpleTypeConstraint::new(
+                self.name(),
+                vec![Arc::new(StringSort), self.vec.clone(), Arc::new(I64Sort)],
+                span.clone(),
+            )
+            .into_box()
+        }
+
+        fn apply(
+            &self,
+            values: &[Value],
+            _sorts: (&[ArcSort], &ArcSort),
+            _egraph: Option<&mut EGraph>,
+        ) -> Option<Value> {
+            let costs = Vec::<Value>::load(&self.vec, &values[1]);
+            let max = costs.iter().map(|c| i64::load(&I64Sort, c)).max();
+            (max.unwrap_or(0) + 1).store(&I64Sort)
+        }
+    }
+
+    #[test]
+    fn test_extract_with_cost_fn() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                "(sort IntVec (Vec i64))
+                 (sort CostFn (UnstableFn (String IntVec) i64))",
+            )
+            .unwrap();
+        let vec = egraph
+            .get_sort_by(|s: &Arc<VecSort>| s.element_name() == I64Sort.name())
+            .unwrap();
+        egraph.add_primitive(DepthCost { vec });
+        egraph
+            .parse_and_run_program(
+                None,
+                "(datatype Math (Num i64) (Neg Math) (Add Math Math))
+                 (let deep (Neg (Neg (Neg (Num 1)))))
+                 (let wide (Add (Add (Num 1) (Num 1)) (Add (Num 1) (Num 1))))
+                 (union deep wide)
+                 (let depth (unstable-fn \"depth-cost\"))
+                 (function table-cost (String IntVec) i64 :no-merge)
+                 (let table (unstable-fn \"table-cost\"))",
+            )
+            .unwrap();
+        let mut eval = |name: &str| egraph.eval_expr(&var!(name)).unwrap();
+        let (sort, deep) = eval("deep");
+        let (cost_fn_sort, depth) = eval("depth");
+        let (_, table) = eval("table");
+
+        // The smallest term is the deepest one
+        let mut termdag = TermDag::default();
+        let (_, smallest) = egraph.extract(deep, &mut termdag, &sort).unwrap();
+        assert_eq!(termdag.to_string(&smallest), "(Neg (Neg (Neg (Num 1))))");
+
+        let (cost, shallowest) = egraph
+            .extract_with_cost_fn(deep, &mut termdag, &sort, &cost_fn_sort, depth)
+            .unwrap();
+        assert_eq!(
+            termdag.to_string(&shallowest),
+            "(Add (Add (Num 1) (Num 1)) (Add (Num 1) (Num 1)))"
+        );
+        // Literals cost 1, so each level of nodes above them adds 1
+        assert_eq!(cost, 4);
+
+        assert!(matches!(
+            egraph.extract_with_cost_fn(deep, &mut termdag, &sort, &sort, deep),
+            Err(Error::NotFunctionSort(_))
+        ));
+        // Calling a declared function could change the e-graph
+        assert!(matches!(
+            egraph.extract_with_cost_fn(deep, &mut termdag, &sort, &cost_fn_sort, table),
+            Err(Error::InvalidCostFn(..))
+        ));
+    }
+
+    #[test]
+    fn test_adaptive_backoff() {
+        let (mut productive, mut dormant) = (Backoff::default(), Backoff::default());
+        let (mut productive_runs, mut dormant_runs) = (0, 0);
+        for _round in 0..16 {
+            if productive.should_run() {
+                productive_runs += 1;
+                productive.record(true);
+            }
+            if dormant.should_run() {
+                dormant_runs += 1;
+                dormant.record(false);
+            }
+        }
+        assert_eq!(productive_runs, 16);
+        // runs in rounds 0, 2, 5, and 10
+        assert_eq!(dormant_runs, 4);
+
+        // resetting only ends the current skip, so another run without new facts
+        // skips for twice as long as before
+        dormant.reset();
+        assert!(dormant.should_run());
+        dormant.record(false);
+        assert_eq!(dormant.skip, 16);
+
+        // a run with new facts resets the backoff
+        dormant.reset();
+        assert!(dormant.should_run());
+        dormant.record(true);
+        assert!(dormant.should_run());
+        dormant.record(false);
+        assert_eq!(dormant.skip, 1);
+    }
+
+    #[test]
+    fn test_primitive_accept() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                "(sort IntVec (Vec i64)) (sort IntFn (UnstableFn (i64) i64))",
+            )
+            .unwrap();
+        let typeinfo = &egraph.type_info;
+        let accepts = |name: &str, sorts: &[&str]| {
+            let sorts: Vec<ArcSort> = sorts
+                .iter()
+                .map(|s| typeinfo.sorts[&Symbol::from(*s)].clone())
+                .collect();
+            typeinfo.primitives[&Symbol::from(name)]
+                .iter()
+                .any(|p| p.accept(&sorts, typeinfo))
+        };
+        assert!(accepts("+", &["i64", "i64", "i64"]));
+        assert!(!accepts("+", &["i64", "String", "i64"]));
+        assert!(accepts("vec-of", &["i64", "i64", "IntVec"]));
+        assert!(accepts("vec-length", &["IntVec", "i64"]));
+        assert!(!accepts("vec-length", &["IntFn", "i64"]));
+        assert!(accepts("unstable-fn", &["String", "IntFn"]));
+        assert!(accepts("unstable-app", &["IntFn", "i64", "i64"]));
+        assert!(!accepts("unstable-app", &["IntFn", "String", "i64"]));
+    }
+
     lazy_static! {
         pub static ref RT: Mutex<EGraph> = Mutex::new(EGraph::default());
     }
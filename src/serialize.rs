@@ -67,7 +67,7 @@ impl EGraph {
     /// - Omitted nodes: infinite
     ///
     /// For node IDs:
-    /// - Functions: Function name + hash of input values
+    /// - Functions: `function-`, the offset of the call in the function table, `-`, and the function name
     /// - Args which are eq sorts: Choose one ID from the e-class, distribute roughly evenly.
     /// - Args and outputs values which are primitives: Sort name + hash of value
     ///
@@ -197,10 +197,10 @@ impl EGraph {
     pub fn class_id_to_value(&self, eclass_id: &egraph_serialize::ClassId) -> Value {
         let s = eclass_id.to_string();
         let (tag, bits) = s.split_once('-').unwrap();
-        #[cfg(not(debug_assertions))]
+        #[cfg(not(any(debug_assertions, feature = "value-tags")))]
         let _ = tag;
         Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: tag.into(),
             bits: bits.parse().unwrap(),
         }
@@ -215,7 +215,7 @@ impl EGraph {
         match node {
             SerializedNode::Function { name, offset } => {
                 assert!(sort.is_none());
-                format!("{}{}", name.to_string().to_lowercase(), offset).into()
+                format!("function-{}-{}", offset, name).into()
             }
             SerializedNode::Primitive(value) => format!(
                 "primitive-{}",
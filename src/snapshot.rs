@@ -0,0 +1,95 @@
+use crate::{util::HashMap, *};
+
+/// A lightweight record of an e-graph's state, used as the baseline for [`EGraph::diff`].
+///
+/// Unlike [`EGraph::push`], this does not copy any tables: it only remembers the
+/// canonical id of every e-class and how many values each sort had interned.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    leaders: Vec<Id>,
+    num_interned: HashMap<Symbol, usize>,
+}
+
+/// What changed in an e-graph since a [`Snapshot`] was taken.
+#[derive(Debug, Clone, Default)]
+pub struct EGraphDiff {
+    /// Ids created since the snapshot which are still canonical.
+    pub new_eclasses: Vec<Id>,
+    /// E-classes that were canonical at the snapshot but have since been merged,
+    /// as pairs of the old canonical id and its current canonical id.
+    pub new_unions: Vec<(Id, Id)>,
+    /// Values interned by container sorts (e.g. closures) since the snapshot,
+    /// ordered by sort name and then by when they were interned.
+    pub new_entries: Vec<(ArcSort, Value)>,
+}
+
+impl EGraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.new_eclasses.is_empty() && self.new_unions.is_empty() && self.new_entries.is_empty()
+    }
+}
+
+impl EGraph {
+    /// Record the current state of the e-graph to later compare against with [`EGraph::diff`].
+    pub fn snapshot(&self) -> Snapshot {
+        let leaders = (0..self.unionfind.num_ids() as Id)
+            .map(|id| self.unionfind.find(id))
+            .collect();
+        let num_interned = self
+            .type_info
+            .sorts
+            .iter()
+            .map(|(name, sort)| (*name, sort.num_interned()))
+            .collect();
+        Snapshot {
+            leaders,
+            num_interned,
+        }
+    }
+
+    /// List the e-classes, unions, and container values added since `baseline` was taken.
+    ///
+    /// The baseline must have been taken from this e-graph, or from one it was pushed from.
+    pub fn diff(&self, baseline: &Snapshot) -> EGraphDiff {
+        let old_ids = baseline.leaders.len() as Id;
+        let new_eclasses = (old_ids..self.unionfind.num_ids() as Id)
+            .filter(|id| self.unionfind.find(*id) == *id)
+            .collect();
+        let new_unions = (0..old_ids)
+            .filter(|id| baseline.leaders[*id as usize] == *id)
+            .filter_map(|id| {
+                let leader = self.unionfind.find(id);
+                (leader != id).then_some((id, leader))
+            })
+            .collect();
+
+        // Skip aliases, which would list the values of the sort they alias again
+        let mut sorts: Vec<_> = self
+            .type_info
+            .sorts
+            .iter()
+            .filter(|(name, sort)| **name == sort.name())
+            .collect();
+        sorts.sort_by_key(|(name, _)| name.as_str());
+        let mut new_entries = vec![];
+        for (name, sort) in sorts {
+            let start = baseline.num_interned.get(name).copied().unwrap_or(0);
+            for i in start..sort.num_interned() {
+                new_entries.push((
+                    sort.clone(),
+                    Value {
+                        #[cfg(any(debug_assertions, feature = "value-tags"))]
+                        tag: sort.name(),
+                        bits: i as u64,
+                    },
+                ));
+            }
+        }
+
+        EGraphDiff {
+            new_eclasses,
+            new_unions,
+            new_entries,
+        }
+    }
+}
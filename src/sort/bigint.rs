@@ -24,6 +24,10 @@ impl Sort for BigIntSort {
         self
     }
 
+    fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        Z::load(self, a).cmp(&Z::load(self, b))
+    }
+
     #[rustfmt::skip]
     fn register_primitives(self: Arc<Self>, eg: &mut TypeInfo) {
         type Opt<T=()> = Option<T>;
@@ -93,7 +97,7 @@ impl IntoSort for Z {
     fn store(self, _sort: &Self::Sort) -> Option<Value> {
         let (i, _) = INTS.lock().unwrap().insert_full(self);
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: BigIntSort.name(),
             bits: i as u64,
         })
@@ -20,6 +20,8 @@ lazy_static! {
 /// - Con/Destruction: `bigrat`, `numer`, `denom`
 /// - Comparisons: `<`, `>`, `<=`, `>=`
 /// - Other: `min`, `max`, `to-f64`
+/// - Series: `(rational-harmonic n)` is the exact `n`th harmonic number `1 + 1/2 + ... + 1/n`,
+///   which is zero for `n = 0` and fails for negative `n`
 #[derive(Debug)]
 pub struct BigRatSort;
 
@@ -32,6 +34,10 @@ impl Sort for BigRatSort {
         self
     }
 
+    fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        Q::load(self, a).cmp(&Q::load(self, b))
+    }
+
     #[rustfmt::skip]
     fn register_primitives(self: Arc<Self>, eg: &mut TypeInfo) {
         type Opt<T=()> = Option<T>;
@@ -53,6 +59,9 @@ impl Sort for BigRatSort {
         add_primitives!(eg, "numer" = |a: Q| -> Z { a.numer().clone() });
         add_primitives!(eg, "denom" = |a: Q| -> Z { a.denom().clone() });
         add_primitives!(eg, "to-f64" = |a: Q| -> f64 { a.to_f64().unwrap() });
+        add_primitives!(eg, "rational-harmonic" = |n: i64| -> Opt<Q> {
+            (n >= 0).then(|| (1..=n).map(|k| Q::new(Z::one(), Z::from(k))).sum())
+        });
 
         add_primitives!(eg, "pow" = |a: Q, b: Q| -> Option<Q> {
             if !b.is_integer() {
@@ -162,7 +171,7 @@ impl IntoSort for Q {
     fn store(self, _sort: &Self::Sort) -> Option<Value> {
         let (i, _) = RATS.lock().unwrap().insert_full(self);
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: BigRatSort.name(),
             bits: i as u64,
         })
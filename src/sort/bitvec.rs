@@ -0,0 +1,225 @@
+use crate::{ast::Literal, constraint::AllEqualTypeConstraint};
+
+use super::*;
+
+/// Fixed-width bitvectors, declared as `(sort BV8 (UnstableBitVec 8))`.
+///
+/// The width must be an integer literal between 1 and 64, and values are packed
+/// into the value's bits. Arithmetic wraps around at the declared width.
+/// Supports these primitives:
+/// - `(bv value width)`: builds a bitvector from the low `width` bits of `value`.
+///   When `width` is a literal it selects the bitvector sort, otherwise the
+///   primitive fails at runtime if `width` is not the width of the sort.
+/// - Bitwise: `bv-and`, `bv-or`, `bv-xor`
+/// - `bv-shl`: shifts left by the second argument, producing zero for shifts of at least `width`
+/// - `bv-add`: wrapping addition
+#[derive(Debug)]
+pub struct BitVecSort {
+    name: Symbol,
+    width: u32,
+}
+
+impl BitVecSort {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.width)
+    }
+
+    fn make_value(&self, bits: u64) -> Value {
+        Value {
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
+            tag: self.name,
+            bits: bits & self.mask(),
+        }
+    }
+}
+
+impl Presort for BitVecSort {
+    fn presort_name() -> Symbol {
+        "UnstableBitVec".into()
+    }
+
+    fn reserved_primitives() -> Vec<Symbol> {
+        vec![
+            "bv".into(),
+            "bv-and".into(),
+            "bv-or".into(),
+            "bv-xor".into(),
+            "bv-shl".into(),
+            "bv-add".into(),
+        ]
+    }
+
+    fn make_sort(
+        _typeinfo: &mut TypeInfo,
+        name: Symbol,
+        args: &[Expr],
+    ) -> Result<ArcSort, TypeError> {
+        match args {
+            [Expr::Lit(_, Literal::Int(width))] if (1..=64).contains(width) => Ok(Arc::new(Self {
+                name,
+                width: *width as u32,
+            })),
+            [arg] => Err(TypeError::DisallowedSort(
+                name,
+                "Bitvector width must be an integer literal between 1 and 64".into(),
+                arg.span(),
+            )),
+            _ => Err(TypeError::DisallowedSort(
+                name,
+                "Bitvector sort must have exactly one width argument".into(),
+                args.first()
+                    .map(|arg| arg.span())
+                    .unwrap_or_else(|| span!()),
+            )),
+        }
+    }
+}
+
+impl Sort for BitVecSort {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn as_arc_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static> {
+        self
+    }
+
+    fn register_primitives(self: Arc<Self>, typeinfo: &mut TypeInfo) {
+        typeinfo.add_primitive(Ctor {
+            name: "bv".into(),
+            bv: self.clone(),
+        });
+        for (name, op) in [
+            ("bv-and", (|a, b| a & b) as fn(u64, u64) -> u64),
+            ("bv-or", |a, b| a | b),
+            ("bv-xor", |a, b| a ^ b),
+            ("bv-shl", |a, b| {
+                u32::try_from(b)
+                    .ok()
+                    .and_then(|b| a.checked_shl(b))
+                    .unwrap_or(0)
+            }),
+            ("bv-add", u64::wrapping_add),
+        ] {
+            typeinfo.add_primitive(BinOp {
+                name: name.into(),
+                bv: self.clone(),
+                op,
+            });
+        }
+    }
+
+    fn extract_term(
+        &self,
+        _egraph: &EGraph,
+        value: Value,
+        _extractor: &Extractor,
+        termdag: &mut TermDag,
+    ) -> Option<(Cost, Term)> {
+        #[cfg(debug_assertions)]
+        debug_assert_eq!(value.tag, self.name());
+
+        let bits = termdag.lit(Literal::Int(value.bits as i64));
+        let width = termdag.lit(Literal::Int(self.width as i64));
+        Some((1, termdag.app("bv".into(), vec![bits, width])))
+    }
+}
+
+// (bv <value> <width>)
+struct Ctor {
+    name: Symbol,
+    bv: Arc<BitVecSort>,
+}
+
+impl PrimitiveLike for Ctor {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        Box::new(BitVecCtorTypeConstraint {
+            bv: self.bv.clone(),
+            inner: SimpleTypeConstraint::new(
+                self.name(),
+                vec![Arc::new(I64Sort), Arc::new(I64Sort), self.bv.clone()],
+                span.clone(),
+            ),
+        })
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let bits = i64::load(&I64Sort, &values[0]);
+        let width = i64::load(&I64Sort, &values[1]);
+        (width == self.bv.width as i64).then(|| self.bv.make_value(bits as u64))
+    }
+}
+
+/// Rules out this sort when the width argument is a literal of another width,
+/// so that `(bv 1 8)` resolves even when several bitvector sorts are declared.
+struct BitVecCtorTypeConstraint {
+    bv: Arc<BitVecSort>,
+    inner: SimpleTypeConstraint,
+}
+
+impl TypeConstraint for BitVecCtorTypeConstraint {
+    fn get(
+        &self,
+        arguments: &[AtomTerm],
+        typeinfo: &TypeInfo,
+    ) -> Vec<Box<dyn Constraint<AtomTerm, ArcSort>>> {
+        match arguments.get(1) {
+            Some(AtomTerm::Literal(span, Literal::Int(width)))
+                if *width != self.bv.width as i64 =>
+            {
+                vec![constraint::impossible(
+                    constraint::ImpossibleConstraint::WidthMismatch {
+                        sort: self.bv.clone(),
+                        width: *width,
+                        span: span.clone(),
+                    },
+                )]
+            }
+            _ => self.inner.get(arguments, typeinfo),
+        }
+    }
+}
+
+struct BinOp {
+    name: Symbol,
+    bv: Arc<BitVecSort>,
+    op: fn(u64, u64) -> u64,
+}
+
+impl PrimitiveLike for BinOp {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        AllEqualTypeConstraint::new(self.name(), span.clone())
+            .with_all_arguments_sort(self.bv.clone())
+            .with_exact_length(3)
+            .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        Some(
+            self.bv
+                .make_value((self.op)(values[0].bits, values[1].bits)),
+        )
+    }
+}
@@ -45,7 +45,7 @@ impl IntoSort for bool {
     type Sort = BoolSort;
     fn store(self, _sort: &Self::Sort) -> Option<Value> {
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: BoolSort.name(),
             bits: self as u64,
         })
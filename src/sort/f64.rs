@@ -3,9 +3,20 @@ use crate::ast::Literal;
 use ordered_float::OrderedFloat;
 
 /// 64-bit floating point numbers supporting these primitives:
-/// - Arithmetic: `+`, `-`, `*`, `/`, `%`, `^`, `neg`, `abs`
+/// - Arithmetic: `+`, `-`, `*`, `/`, `%`, `^`, `neg`, `abs`, `sqrt`
+/// - Fused multiply-add: `(fma a b c)` computes `a * b + c` with a single rounding
 /// - Comparisons: `<`, `>`, `<=`, `>=`
+/// - Representation: `(f64-nextafter x toward)` is the float next to `x` in the direction
+///   of `toward`, and `(f64-ulp x)` is the gap between `|x|` and the next larger float
 /// - Other: `min`, `max`, `to-i64`, `to-string`
+///
+/// Values are stored as their bit pattern, normalized so that equal floats
+/// are equal values: every NaN is stored as the same quiet NaN and `-0.0`
+/// is stored as `0.0`. As a consequence `NaN` is equal to itself when used
+/// as a value (e.g. as a function input or in `(= a b)`).
+///
+/// The comparisons follow IEEE 754: any comparison involving `NaN` fails.
+/// `min` and `max` return the non-`NaN` argument if only one is `NaN`.
 #[derive(Debug)]
 pub struct F64Sort;
 
@@ -22,6 +33,10 @@ impl Sort for F64Sort {
         self
     }
 
+    fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        OrderedFloat(f64::load(self, a)).cmp(&OrderedFloat(f64::load(self, b)))
+    }
+
     #[rustfmt::skip]
     // We need the closure for division and mod operations, as they can panic.
     // cf https://github.com/rust-lang/rust-clippy/issues/9422
@@ -36,6 +51,7 @@ impl Sort for F64Sort {
         add_primitives!(eg, "%" = |a: f64, b: f64| -> Opt<f64> { (b != 0.0).then(|| a % b) });
         add_primitives!(eg, "^" = |a: f64, b: f64| -> f64 { a.powf(b) });
         add_primitives!(eg, "neg" = |a: f64| -> f64 { -a });
+        add_primitives!(eg, "fma" = |a: f64, b: f64, c: f64| -> f64 { a.mul_add(b, c) });
 
         add_primitives!(eg, "<" = |a: f64, b: f64| -> Opt { (a < b).then(|| ()) });
         add_primitives!(eg, ">" = |a: f64, b: f64| -> Opt { (a > b).then(|| ()) });
@@ -45,6 +61,10 @@ impl Sort for F64Sort {
         add_primitives!(eg, "min" = |a: f64, b: f64| -> f64 { a.min(b) });
         add_primitives!(eg, "max" = |a: f64, b: f64| -> f64 { a.max(b) });
         add_primitives!(eg, "abs" = |a: f64| -> f64 { a.abs() });
+        add_primitives!(eg, "sqrt" = |a: f64| -> f64 { a.sqrt() });
+
+        add_primitives!(eg, "f64-nextafter" = |a: f64, toward: f64| -> f64 { next_after(a, toward) });
+        add_primitives!(eg, "f64-ulp" = |a: f64| -> f64 { ulp(a) });
 
         // `to-f64` should be in `i64.rs`, but `F64Sort` wouldn't exist yet
         add_primitives!(eg, "to-f64" = |a: i64| -> f64 { a as f64 });
@@ -70,13 +90,59 @@ impl Sort for F64Sort {
     }
 }
 
+/// Returns the bits used to store `f` as a [`Value`].
+///
+/// All NaNs share a single bit pattern and `-0.0` is mapped to `0.0`,
+/// so that floats that compare equal also hash equal.
+pub(crate) fn f64_to_bits(f: f64) -> u64 {
+    if f.is_nan() {
+        f64::NAN.to_bits()
+    } else if f == 0.0 {
+        0.0f64.to_bits()
+    } else {
+        f.to_bits()
+    }
+}
+
+/// The float next to `x` in the direction of `toward`, like C's `nextafter`.
+///
+/// Positive floats are ordered like their bits, and negative floats in reverse,
+/// so stepping the bits steps through every float including subnormals.
+fn next_after(x: f64, toward: f64) -> f64 {
+    if x.is_nan() || toward.is_nan() {
+        f64::NAN
+    } else if x == toward {
+        toward
+    } else if x == 0.0 {
+        // The smallest subnormal, with the sign of the direction
+        f64::from_bits(1).copysign(toward)
+    } else if (x < toward) == (x > 0.0) {
+        f64::from_bits(x.to_bits() + 1)
+    } else {
+        f64::from_bits(x.to_bits() - 1)
+    }
+}
+
+/// The unit in the last place of `x`: the gap between `|x|` and the next larger float.
+/// For the largest finite float this is the gap below it, and it is infinite for infinities.
+fn ulp(x: f64) -> f64 {
+    let x = x.abs();
+    if x.is_nan() || x.is_infinite() {
+        x
+    } else if x == f64::MAX {
+        x - next_after(x, 0.0)
+    } else {
+        next_after(x, f64::INFINITY) - x
+    }
+}
+
 impl IntoSort for f64 {
     type Sort = F64Sort;
     fn store(self, _sort: &Self::Sort) -> Option<Value> {
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: F64Sort.name(),
-            bits: self.to_bits(),
+            bits: f64_to_bits(self),
         })
     }
 }
@@ -87,3 +153,59 @@ impl FromSort for f64 {
         f64::from_bits(value.bits)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: [f64; 10] = [
+        0.0,
+        -0.0,
+        1.5,
+        -1.5,
+        f64::MIN_POSITIVE,
+        f64::MAX,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+        -f64::NAN,
+    ];
+
+    #[test]
+    fn test_equal_floats_store_equal() {
+        for a in SAMPLES {
+            for b in SAMPLES {
+                let same = a == b || (a.is_nan() && b.is_nan());
+                assert_eq!(
+                    a.store(&F64Sort) == b.store(&F64Sort),
+                    same,
+                    "{a:?} vs {b:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_store_load_roundtrip() {
+        for a in SAMPLES {
+            let loaded = f64::load(&F64Sort, &a.store(&F64Sort).unwrap());
+            assert!(loaded == a || (loaded.is_nan() && a.is_nan()));
+            assert_eq!(f64_to_bits(loaded), f64_to_bits(a));
+        }
+    }
+
+    #[test]
+    fn test_nan_payloads_are_normalized() {
+        let nan_with_payload = f64::from_bits(f64::NAN.to_bits() | 0x1234);
+        assert!(nan_with_payload.is_nan());
+        assert_eq!(f64_to_bits(nan_with_payload), f64_to_bits(f64::NAN));
+        assert_eq!(
+            Value::from(OrderedFloat(nan_with_payload)),
+            f64::NAN.store(&F64Sort).unwrap()
+        );
+        assert_eq!(
+            Value::from(OrderedFloat(-0.0)),
+            0.0.store(&F64Sort).unwrap()
+        );
+    }
+}
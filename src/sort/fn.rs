@@ -10,12 +10,53 @@
 //! The value is stored similar to the `vec` sort, as an index into a set, where each item in
 //! the set is a `(Symbol, Vec<Value>)` pairs. The Symbol is the function name, and the `Vec<Value>` is
 //! the list of partially applied arguments.
-use std::sync::Mutex;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Mutex, MutexGuard};
 
-use crate::ast::Literal;
+use crate::{
+    ast::Literal, core::SpecializedPrimitive, extract::ExtractionError, typechecking::FuncType,
+};
 
 use super::*;
 
+lazy_static! {
+    static ref ELLIPSIS: Symbol = "...".into();
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// The function sorts whose interning lock this thread holds, by address.
+    static HELD_LOCKS: std::cell::RefCell<Vec<usize>> = const { std::cell::RefCell::new(vec![]) };
+}
+
+/// The interning lock of a function sort, see [`FunctionSort::lock_functions`].
+struct FunctionsGuard<'a> {
+    functions: MutexGuard<'a, IndexSet<ValueFunction>>,
+    #[cfg(debug_assertions)]
+    sort: usize,
+}
+
+impl Deref for FunctionsGuard<'_> {
+    type Target = IndexSet<ValueFunction>;
+    fn deref(&self) -> &Self::Target {
+        &self.functions
+    }
+}
+
+impl DerefMut for FunctionsGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.functions
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for FunctionsGuard<'_> {
+    fn drop(&mut self) {
+        HELD_LOCKS.with(|held| held.borrow_mut().retain(|sort| *sort != self.sort));
+    }
+}
+
 /// A function value is a name of a function, a list of partially applied arguments (values and sort)
 /// Note that we must store the actual arcsorts so we can return them when returning inner values
 /// and when canonicalizing
@@ -28,6 +69,12 @@ impl ValueFunction {
     fn hashable(&self) -> (Symbol, Vec<&Value>) {
         (self.0, self.1.iter().map(|(_, v)| v).collect())
     }
+
+    /// Like [`ValueFunction::hashable`], but owning the values, to use as a key elsewhere
+    fn hashable_owned(&self) -> (Symbol, Vec<Value>) {
+        let (name, args) = self.hashable();
+        (name, args.into_iter().copied().collect())
+    }
 }
 
 impl Hash for ValueFunction {
@@ -44,38 +91,670 @@ impl PartialEq for ValueFunction {
 
 impl Eq for ValueFunction {}
 
+/// Called with the function name and the number of partial args whenever a new
+/// function value is interned. See [`EGraph::set_fn_intern_observer`].
+pub type InternObserver = Box<dyn FnMut(Symbol, usize) + Send>;
+
+/// Shared by all the function sorts of an e-graph, so that an observer set on the
+/// e-graph also sees the sorts declared after it.
+#[derive(Clone, Default)]
+pub(crate) struct InternObserverSlot(Arc<Mutex<Option<InternObserver>>>);
+
+impl InternObserverSlot {
+    pub(crate) fn set(&self, observer: Option<InternObserver>) {
+        *self.0.lock().unwrap() = observer;
+    }
+
+    fn notify(&self, name: Symbol, captured_count: usize) {
+        if let Some(observer) = self.0.lock().unwrap().as_mut() {
+            observer(name, captured_count);
+        }
+    }
+}
+
+impl Debug for InternObserverSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InternObserverSlot(..)")
+    }
+}
+
+enum SignatureMismatch {
+    Arity,
+    Sorts,
+}
+
+/// A function sort, declared like `(sort IntToString (UnstableFn (i64) String))`.
+///
+/// The last input may be followed by `...` to make it repeat, like
+/// `(UnstableFn (String ...) String)`. Its values can only wrap primitives, since declared
+/// functions always take a fixed number of args. The output
+/// and the inputs may be sorts written out in place, like `(UnstableFn ((Vec i64)) i64)`,
+/// which are declared as `<name>Output` and `<name>Input<i>`, or are aliases for a sort
+/// already declared the same way. A function sort with the same inputs and output as an
+/// earlier one is an alias for it.
 #[derive(Debug)]
 pub struct FunctionSort {
     name: Symbol,
     // Public so that other primitive sorts (external or internal) can find a function sort by the sorts of its inputs/output
+    /// For a variadic sort, the last input is the repeated one, so applying a value to
+    /// exactly one arg of each input is valid either way.
     pub inputs: Vec<ArcSort>,
     pub output: ArcSort,
+    /// Whether the last input may be repeated any number of times, including zero.
+    pub variadic: bool,
+    /// The interned function values, indexed by `Value::bits`.
+    ///
+    /// The lock is only held while reading or inserting entries, never while calling a
+    /// function, notifying the observer, or canonicalizing partial args. So primitives may
+    /// load values of this sort and store new ones in any order within one call, as
+    /// `unstable-app-all` does when mapping a function over a vec of function values of
+    /// the sort it returns. Indices are never reused, since entries are only removed when
+    /// popping, so a value loaded earlier stays valid after later stores grow the set.
+    /// Taking the lock again while holding it would deadlock, which debug builds report
+    /// instead, see [`FunctionSort::lock_functions`].
     functions: Mutex<IndexSet<ValueFunction>>,
+    observer: InternObserverSlot,
+    /// How many times a stored function value was already interned.
+    dedup_hits: AtomicUsize,
+    /// Whether any stored value captured a partial arg of an eqsort or container sort.
+    /// Until then, canonicalizing a value of this sort can't change it.
+    captures_eq: AtomicBool,
+    /// Function names that were renamed, to the name they load as, see
+    /// [`FunctionSort::rename_function`].
+    renames: Mutex<HashMap<Symbol, Symbol>>,
+    /// Whether `renames` is not empty, to skip looking names up until then.
+    any_renamed: AtomicBool,
+}
+
+/// How much storing function values reuses already interned ones, see [`FunctionSort::intern_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InternStats {
+    /// The number of distinct function values interned so far. Entries are never removed.
+    pub interned: usize,
+    /// The number of times a stored function value was already interned, for example
+    /// when canonicalizing a value whose partial args did not change.
+    pub dedup_hits: usize,
+}
+
+/// A function value wrapping a primitive, which can be called without an e-graph.
+/// See [`FunctionSort::pure_call`].
+pub(crate) struct PureCall {
+    primitive: Primitive,
+    captured: Vec<Value>,
+    input: Vec<ArcSort>,
+    output: ArcSort,
+}
+
+impl PureCall {
+    /// Call the primitive with the partial args followed by `args`, like in a query.
+    pub(crate) fn call(&self, args: &[Value]) -> Option<Value> {
+        let values: Vec<Value> = self.captured.iter().chain(args).copied().collect();
+        self.primitive
+            .apply(&values, (&self.input, &self.output), None)
+    }
 }
 
 impl FunctionSort {
-    fn get_value(&self, value: &Value) -> ValueFunction {
-        let functions = self.functions.lock().unwrap();
-        functions.get_index(value.bits as usize).unwrap().clone()
+    /// Takes the interning lock. In debug builds, panics instead of deadlocking if this
+    /// thread already holds it.
+    fn lock_functions(&self) -> FunctionsGuard<'_> {
+        #[cfg(debug_assertions)]
+        let sort = self as *const Self as usize;
+        #[cfg(debug_assertions)]
+        HELD_LOCKS.with(|held| {
+            let mut held = held.borrow_mut();
+            assert!(
+                !held.contains(&sort),
+                "re-entrant lock of the function values of {}",
+                self.name
+            );
+            held.push(sort);
+        });
+        FunctionsGuard {
+            functions: self.functions.lock().unwrap(),
+            #[cfg(debug_assertions)]
+            sort,
+        }
+    }
+
+    /// Sets `captures_eq` if any of the partial args of a stored value could change
+    /// when canonicalized.
+    fn note_captured(&self, args: &[(ArcSort, Value)]) {
+        // A captured function value may itself capture eq values, even if its sort takes
+        // no eq inputs, so count every container
+        if args
+            .iter()
+            .any(|(s, _)| s.is_eq_sort() || s.is_container_sort())
+        {
+            self.captures_eq.store(true, AtomicOrdering::Relaxed);
+        }
+    }
+
+    /// Stores all of `functions` under a single acquisition of the interning lock, and
+    /// returns their values in the same order.
+    fn store_all(&self, functions: Vec<ValueFunction>) -> Vec<Value> {
+        let mut values = Vec::with_capacity(functions.len());
+        let mut new_functions = vec![];
+        {
+            let mut interned = self.lock_functions();
+            for function in functions {
+                self.note_captured(&function.1);
+                let name = function.0;
+                let captured_count = function.1.len();
+                let (i, is_new) = interned.insert_full(function);
+                if is_new {
+                    new_functions.push((name, captured_count));
+                } else {
+                    self.dedup_hits.fetch_add(1, AtomicOrdering::Relaxed);
+                }
+                values.push(Value {
+                    #[cfg(any(debug_assertions, feature = "value-tags"))]
+                    tag: self.name,
+                    bits: i as u64,
+                });
+            }
+        }
+        // Only notify after releasing the lock, as in `store`
+        for (name, captured_count) in new_functions {
+            self.observer.notify(name, captured_count);
+        }
+        values
+    }
+
+    /// The number of partial args that make the declared function `name` into a value
+    /// of this sort, if its first inputs are all of sort `arg` and the rest match this sort.
+    fn table_captured_count(&self, func_type: &FuncType, arg: &ArcSort) -> Option<usize> {
+        let captured_count = func_type.input.len().checked_sub(self.inputs.len())?;
+        (self.check_signature(func_type, captured_count).is_ok()
+            && func_type.input[..captured_count]
+                .iter()
+                .all(|input| input.name() == arg.name()))
+        .then_some(captured_count)
+    }
+
+    /// Registers `unstable-fn-table` making values of this sort from a vec of rows of
+    /// partial args, `rows`, into a vec of values of this sort, `result`.
+    pub(crate) fn register_table(
+        self: Arc<Self>,
+        rows: Arc<VecSort>,
+        result: Arc<VecSort>,
+        typeinfo: &mut TypeInfo,
+    ) {
+        let Ok(row) = rows.element().as_arc_any().downcast::<VecSort>() else {
+            return;
+        };
+        typeinfo.add_primitive(Table {
+            name: "unstable-fn-table".into(),
+            function: self,
+            rows,
+            row,
+            result,
+        });
+    }
+
+    /// The function value that `value` refers to, or `None` if there is none, e.g. for
+    /// a value read from a corrupted snapshot. A value of another sort is only caught
+    /// with the `checked-values` feature, or if its index is out of range.
+    fn get_value(&self, value: &Value) -> Option<ValueFunction> {
+        // The index of a value of another sort would read the wrong table
+        #[cfg(feature = "checked-values")]
+        if value.tag != self.name {
+            return None;
+        }
+        let functions = self.lock_functions();
+        let ValueFunction(name, args) = functions.get_index(value.bits as usize)?.clone();
+        drop(functions);
+        Some(ValueFunction(self.renamed(name), args))
+    }
+
+    /// The name that function values wrapping `name` load as.
+    fn renamed(&self, name: Symbol) -> Symbol {
+        if !self.any_renamed.load(AtomicOrdering::Relaxed) {
+            return name;
+        }
+        self.renames
+            .lock()
+            .unwrap()
+            .get(&name)
+            .copied()
+            .unwrap_or(name)
+    }
+
+    /// Makes every function value of this sort that wraps `old` wrap `new` instead,
+    /// whether it was stored before or after the rename.
+    ///
+    /// The e-graph does not rename its tables, so `new` should already be declared and
+    /// compute what `old` did. Values stored before keep their index, but load with the
+    /// new name, so they apply `new`. Canonicalizing one re-interns it under the new
+    /// name, which makes it equal to the same function value stored after the rename.
+    /// Popping does not undo renames.
+    pub fn rename_function(&self, old: Symbol, new: Symbol) {
+        let mut renames = self.renames.lock().unwrap();
+        // Names already renamed to `old` follow it
+        for target in renames.values_mut() {
+            if *target == old {
+                *target = new;
+            }
+        }
+        renames.insert(old, new);
+        // Renaming back leaves names that load as themselves
+        renames.retain(|name, target| name != target);
+        self.any_renamed
+            .store(!renames.is_empty(), AtomicOrdering::Relaxed);
+    }
+
+    /// Whether the declared function `name`, with its first `captured_count` arguments
+    /// partially applied, has the inputs and output of this sort.
+    ///
+    /// Only declared functions and constructors are considered, never primitives.
+    pub fn signature_matches(
+        &self,
+        name: Symbol,
+        captured_count: usize,
+        typeinfo: &TypeInfo,
+    ) -> bool {
+        typeinfo
+            .func_types
+            .get(&name)
+            .is_some_and(|func_type| self.check_signature(func_type, captured_count).is_ok())
+    }
+
+    /// Statistics on the function values interned by this sort. The number of stores
+    /// is the sum of both counts.
+    pub fn intern_stats(&self) -> InternStats {
+        InternStats {
+            interned: self.num_interned(),
+            dedup_hits: self.dedup_hits.load(AtomicOrdering::Relaxed),
+        }
+    }
+
+    /// Whether both sorts take the same inputs and return the same output, in which
+    /// case only the first one declared is used and the other name is an alias for it.
+    pub fn same_signature(&self, other: &FunctionSort) -> bool {
+        self.variadic == other.variadic
+            && self.output.name() == other.output.name()
+            && self
+                .inputs
+                .iter()
+                .map(|s| s.name())
+                .eq(other.inputs.iter().map(|s| s.name()))
+    }
+
+    /// Whether `other` is the sort of this sort's values with one more partial arg, for
+    /// the first input. For a variadic sort whose only input is the repeated one, that
+    /// is the sort itself.
+    pub fn captures_first_into(&self, other: &FunctionSort) -> bool {
+        let remaining = match &self.inputs[..] {
+            [] => return false,
+            [_] if self.variadic => &self.inputs[..],
+            [_, rest @ ..] => rest,
+        };
+        self.variadic == other.variadic
+            && self.output.name() == other.output.name()
+            && remaining
+                .iter()
+                .map(|s| s.name())
+                .eq(other.inputs.iter().map(|s| s.name()))
+    }
+
+    /// Whether `other` is the sort of this sort's values with their first two inputs
+    /// swapped, see `unstable-flip`. Variadic sorts can't be flipped.
+    pub fn flips_into(&self, other: &FunctionSort) -> bool {
+        let [first, second, rest @ ..] = &self.inputs[..] else {
+            return false;
+        };
+        !self.variadic
+            && !other.variadic
+            && self.output.name() == other.output.name()
+            && [second, first]
+                .into_iter()
+                .chain(rest)
+                .map(|s| s.name())
+                .eq(other.inputs.iter().map(|s| s.name()))
+    }
+
+    /// The sorts of the function values that the curried form of this sort returns when
+    /// applied to one arg at a time, starting with `curried` itself, if that is the curried
+    /// form, see `unstable-curry`. Only sorts of at least two fixed inputs are curried.
+    fn curried_levels(&self, curried: &Arc<FunctionSort>) -> Option<Vec<Arc<FunctionSort>>> {
+        if self.variadic || self.inputs.len() < 2 {
+            return None;
+        }
+        let mut levels = vec![];
+        let mut level = curried.clone();
+        for (i, input) in self.inputs.iter().enumerate() {
+            if level.variadic || level.inputs.len() != 1 || level.inputs[0].name() != input.name() {
+                return None;
+            }
+            levels.push(level.clone());
+            if i + 1 < self.inputs.len() {
+                level = level.output.clone().as_arc_any().downcast().ok()?;
+            }
+        }
+        (level.output.name() == self.output.name()).then_some(levels)
+    }
+
+    /// Registers `unstable-curry` from this sort into the first of `levels`, and the
+    /// `unstable-curried` primitive that values of each level wrap.
+    fn register_curry(self: Arc<Self>, levels: Vec<Arc<FunctionSort>>, typeinfo: &mut TypeInfo) {
+        typeinfo.add_primitive(Curry {
+            name: "unstable-curry".into(),
+            function: self.clone(),
+            result: levels[0].clone(),
+        });
+        for given in 1..=self.inputs.len() {
+            typeinfo.add_primitive(Curried {
+                name: CURRIED.into(),
+                function: self.clone(),
+                given,
+                next: levels.get(given).cloned(),
+            });
+        }
+    }
+
+    /// The least number of args this function can be applied to.
+    pub fn min_arity(&self) -> usize {
+        self.inputs.len() - usize::from(self.variadic)
+    }
+
+    /// The sorts of `n_args` args applied to this function, or `None` if it can't take that many.
+    pub(crate) fn arg_sorts(&self, n_args: usize) -> Option<Vec<ArcSort>> {
+        if !self.variadic {
+            return (n_args == self.inputs.len()).then(|| self.inputs.clone());
+        }
+        let (repeated, fixed) = self.inputs.split_last().unwrap();
+        let n_repeated = n_args.checked_sub(fixed.len())?;
+        Some(
+            fixed
+                .iter()
+                .cloned()
+                .chain(std::iter::repeat(repeated.clone()).take(n_repeated))
+                .collect(),
+        )
+    }
+
+    fn check_signature(
+        &self,
+        func_type: &FuncType,
+        captured_count: usize,
+    ) -> Result<(), SignatureMismatch> {
+        // declared functions always take a fixed number of args
+        if self.variadic {
+            return Err(SignatureMismatch::Arity);
+        }
+        // the number of partial args must match the number of inputs from the func type minus the number from
+        // this function sort
+        if self.inputs.len() + captured_count != func_type.input.len() {
+            return Err(SignatureMismatch::Arity);
+        }
+        // the output type and input types (starting after the partial args) must match between these functions
+        if self.output.name() != func_type.output.name()
+            || self
+                .inputs
+                .iter()
+                .map(|s| s.name())
+                .ne(func_type.input[captured_count..].iter().map(|s| s.name()))
+        {
+            return Err(SignatureMismatch::Sorts);
+        }
+        Ok(())
+    }
+
+    /// All function values interned in this sort, with their function name and
+    /// partially applied arguments.
+    ///
+    /// Interned values are never removed, so this may include values that are no
+    /// longer reachable from any table, as well as non-canonical versions of values
+    /// that were canonicalized after a union.
+    pub fn iter_values(&self) -> impl Iterator<Item = (Value, Symbol, Vec<(ArcSort, Value)>)> {
+        let functions: Vec<_> = self.lock_functions().iter().cloned().collect();
+        let functions: Vec<_> = functions
+            .into_iter()
+            .map(|ValueFunction(name, args)| ValueFunction(self.renamed(name), args))
+            .collect();
+        #[cfg(any(debug_assertions, feature = "value-tags"))]
+        let sort_name = self.name;
+        functions
+            .into_iter()
+            .enumerate()
+            .map(move |(i, ValueFunction(name, args))| {
+                let value = Value {
+                    #[cfg(any(debug_assertions, feature = "value-tags"))]
+                    tag: sort_name,
+                    bits: i as u64,
+                };
+                (value, name, args)
+            })
+    }
+
+    /// Resolve the primitive wrapped by `fn_value`, to call it with one arg per input
+    /// without access to the e-graph.
+    ///
+    /// Returns `None` if the value wraps a declared function instead, since calling
+    /// those may change the e-graph, or if no primitive accepts the args.
+    pub(crate) fn pure_call(&self, fn_value: &Value, typeinfo: &TypeInfo) -> Option<PureCall> {
+        let ValueFunction(name, args) = self.get_value(fn_value)?;
+        if typeinfo.func_types.contains_key(&name) {
+            return None;
+        }
+        let (mut input, captured): (Vec<ArcSort>, Vec<Value>) = args.into_iter().unzip();
+        input.extend(self.inputs.iter().cloned());
+        let types: Vec<_> = input
+            .iter()
+            .cloned()
+            .chain(once(self.output.clone()))
+            .collect();
+        let primitive = typeinfo
+            .primitives
+            .get(&name)?
+            .iter()
+            .find(|primitive| primitive.accept(&types, typeinfo))?
+            .clone();
+        Some(PureCall {
+            primitive,
+            captured,
+            input,
+            output: self.output.clone(),
+        })
     }
 
     /// Apply the function to the values
     ///
     /// Public so that other primitive sorts (external or internal) can use this to apply functions
-    pub fn apply(&self, fn_value: &Value, arg_values: &[Value], egraph: &mut EGraph) -> Value {
-        let ValueFunction(name, args) = self.get_value(fn_value);
+    ///
+    /// The call runs like an action: a constructor returns the e-class of its existing row
+    /// for the canonical args, and makes a new e-class only if there is none. The result is
+    /// canonical. Unions made during the call are not rebuilt here, so congruences they imply
+    /// (and rules they enable) only take effect at the next rebuild, as for any other action.
+    ///
+    /// No interning lock is held during the call, so the function may itself store and
+    /// apply values of this sort, e.g. a primitive calling back into the e-graph.
+    ///
+    /// Fails if the function value or the number of arguments does not match this sort,
+    /// or if the call fails, e.g. because a primitive in it fails, or produces no value.
+    pub fn apply(
+        &self,
+        fn_value: &Value,
+        arg_values: &[Value],
+        egraph: &mut EGraph,
+    ) -> Result<Value, Error> {
+        let arg_sorts = self
+            .arg_sorts(arg_values.len())
+            .ok_or(Error::FunctionValueArity(
+                self.name,
+                self.min_arity(),
+                arg_values.len(),
+            ))?;
+        // Only builds that tag values with their sort can check it
+        #[cfg(any(debug_assertions, feature = "value-tags"))]
+        for (value, sort) in once((fn_value, self.name))
+            .chain(arg_values.iter().zip(arg_sorts.iter().map(|s| s.name())))
+        {
+            if value.tag != sort {
+                return Err(Error::InvalidValue(sort, *value));
+            }
+        }
+        let ValueFunction(name, args) = self
+            .get_value(fn_value)
+            .ok_or(Error::InvalidValue(self.name, *fn_value))?;
+        // The identity returns its arg without looking up a primitive
+        if name.as_str() == IDENTITY && args.is_empty() && arg_values.len() == 1 {
+            let mut output = arg_values[0];
+            self.output.canonicalize(&mut output, &egraph.unionfind);
+            return Ok(output);
+        }
         let types: Vec<_> = args
             .iter()
             .map(|(sort, _)| sort.clone())
-            .chain(self.inputs.clone())
+            .chain(arg_sorts)
             .chain(once(self.output.clone()))
             .collect();
-        let values = args
+        // Look up rows by canonical args, since the values we were given may be stale
+        let values = types
             .iter()
-            .map(|(_, v)| *v)
-            .chain(arg_values.iter().cloned())
+            .zip(
+                args.iter()
+                    .map(|(_, v)| *v)
+                    .chain(arg_values.iter().cloned()),
+            )
+            .map(|(sort, mut value)| {
+                sort.canonicalize(&mut value, &egraph.unionfind);
+                value
+            })
             .collect();
-        call_fn(egraph, &name, types, values)
+        let mut output = call_fn(egraph, &name, types, values)?;
+        self.output.canonicalize(&mut output, &egraph.unionfind);
+        Ok(output)
+    }
+
+    /// Extract the term for a function value like [`Sort::extract_term`], but say which
+    /// partial arg could not be extracted if there is one.
+    ///
+    /// With [`Extractor::new_nested_apps`], a curried value is rendered as the curried
+    /// function applied to one of its args at a time.
+    pub fn try_extract_term(
+        &self,
+        value: Value,
+        extractor: &Extractor,
+        termdag: &mut TermDag,
+    ) -> Result<(Cost, Term), ExtractionError> {
+        let ValueFunction(name, inputs) = self
+            .get_value(&value)
+            .ok_or(ExtractionError::InvalidValue(self.name, value))?;
+        let mut args = vec![termdag.lit(Literal::String(name))];
+        let mut costs = vec![];
+        for (index, (sort, value)) in inputs.into_iter().enumerate() {
+            let (cost, term) =
+                extractor
+                    .find_best(value, termdag, &sort)
+                    .ok_or(ExtractionError::PartialArg {
+                        function: name,
+                        index,
+                        sort: sort.name(),
+                    })?;
+            args.push(term);
+            costs.push(cost);
+        }
+
+        let term = if extractor.nested_apps() && name.as_str() == CURRIED && args.len() > 2 {
+            // Applying a curried value to one more arg returns the value that also has it
+            // as a partial arg, so only the curried function is a partial arg of the base
+            let rest = args.split_off(2);
+            let base = termdag.app("unstable-fn".into(), args);
+            rest.into_iter().fold(base, |function, arg| {
+                termdag.app("unstable-app".into(), vec![function, arg])
+            })
+        } else {
+            termdag.app("unstable-fn".into(), args)
+        };
+        Ok((extractor.node_cost(&term, termdag, 1, &costs), term))
+    }
+
+    /// The function value that `term` denotes, which is the inverse of extracting it: the
+    /// term must be `(unstable-fn "name" args...)`, and each arg is evaluated in `egraph`
+    /// like an expression, so it may be a container or another function value.
+    ///
+    /// Fails if the term does not have that form, if evaluating an arg fails, or if `name`
+    /// is a declared function that values of this sort can't wrap with these partial args.
+    /// A primitive is not checked until the value is applied.
+    pub fn term_to_value(
+        &self,
+        term: &Term,
+        termdag: &TermDag,
+        egraph: &mut EGraph,
+    ) -> Result<Value, Error> {
+        let invalid = || Error::InvalidFunctionTerm(self.name, termdag.to_string(term));
+        let Term::App(head, children) = term else {
+            return Err(invalid());
+        };
+        let [name, args @ ..] = &children[..] else {
+            return Err(invalid());
+        };
+        let Term::Lit(Literal::String(name)) = termdag.get(*name) else {
+            return Err(invalid());
+        };
+        if head.as_str() != "unstable-fn" {
+            return Err(invalid());
+        }
+        let mut captured = vec![];
+        for arg in args {
+            let expr = termdag.term_to_expr(termdag.get(*arg), span!());
+            captured.push(egraph.eval_expr(&expr)?);
+        }
+        let typeinfo = egraph.type_info();
+        if let Some(func_type) = typeinfo.func_types.get(name) {
+            let sorts_match = func_type
+                .input
+                .iter()
+                .zip(&captured)
+                .all(|(input, (sort, _))| input.name() == sort.name());
+            if !sorts_match || !self.signature_matches(*name, captured.len(), typeinfo) {
+                return Err(invalid());
+            }
+        }
+        ValueFunction(*name, captured)
+            .store(self)
+            .ok_or_else(invalid)
+    }
+
+    /// Like [`FunctionSort::apply`], but first checks that the args were given for the
+    /// inputs of this sort, as `arg_sorts`, instead of trusting the caller.
+    ///
+    /// Fails if there are not as many sorts as args, if this sort does not take that many
+    /// args, or if any of the sorts is not the input at its position.
+    pub fn apply_checked(
+        &self,
+        fn_value: &Value,
+        arg_values: &[Value],
+        arg_sorts: &[ArcSort],
+        egraph: &mut EGraph,
+    ) -> Result<Value, Error> {
+        if arg_sorts.len() != arg_values.len() {
+            // Report the number of sorts as the number of args expected
+            return Err(Error::FunctionValueArity(
+                self.name,
+                arg_sorts.len(),
+                arg_values.len(),
+            ));
+        }
+        let expected = self
+            .arg_sorts(arg_sorts.len())
+            .ok_or(Error::FunctionValueArity(
+                self.name,
+                self.min_arity(),
+                arg_values.len(),
+            ))?;
+        for (i, (expected, actual)) in expected.iter().zip(arg_sorts).enumerate() {
+            if expected.name() != actual.name() {
+                return Err(Error::FunctionValueArgSort(
+                    self.name,
+                    i,
+                    expected.name(),
+                    actual.name(),
+                ));
+            }
+        }
+        self.apply(fn_value, arg_values, egraph)
     }
 }
 
@@ -85,7 +764,31 @@ impl Presort for FunctionSort {
     }
 
     fn reserved_primitives() -> Vec<Symbol> {
-        vec!["unstable-fn".into(), "unstable-app".into()]
+        vec![
+            "unstable-fn".into(),
+            "unstable-app".into(),
+            "fn-well-typed?".into(),
+            "unstable-fn-name".into(),
+            "unstable-fn-arity".into(),
+            "unstable-fn-fewer-captured".into(),
+            "unstable-fn-rename".into(),
+            "fn-rebind".into(),
+            "apply-memo-into".into(),
+            "unstable-fn-of".into(),
+            "unstable-fn-eq".into(),
+            "unstable-app-memo".into(),
+            "unstable-fn-partial".into(),
+            "unstable-fn-lt".into(),
+            "unstable-flip".into(),
+            FLIPPED.into(),
+            "unstable-fn-table".into(),
+            "unstable-curry".into(),
+            CURRIED.into(),
+            "unstable-identity".into(),
+            IDENTITY.into(),
+            "unstable-const".into(),
+            CONSTANT.into(),
+        ]
     }
 
     fn make_sort(
@@ -93,41 +796,101 @@ impl Presort for FunctionSort {
         name: Symbol,
         args: &[Expr],
     ) -> Result<ArcSort, TypeError> {
-        if let [inputs, Expr::Var(span, output)] = args {
-            let output_sort = typeinfo
-                .sorts
-                .get(output)
-                .ok_or(TypeError::UndefinedSort(*output, span.clone()))?;
-
-            let input_sorts = match inputs {
-                Expr::Call(_, first, rest_args) => {
-                    let all_args = once(first).chain(rest_args.iter().map(|arg| {
-                        if let Expr::Var(_, arg) = arg {
-                            arg
-                        } else {
-                            panic!("function sort must be called with list of input sorts");
-                        }
-                    }));
-                    all_args
-                        .map(|arg| {
-                            typeinfo
+        if let [inputs, output] = args {
+            let span = output.span();
+            let output_sort = match output {
+                Expr::Var(_, output) => typeinfo
+                    .sorts
+                    .get(output)
+                    .ok_or(TypeError::UndefinedSort(*output, span.clone()))?
+                    .clone(),
+                // Declared as `<name>Output`, see `TypeInfo::declare_nested_sort`
+                Expr::Call(_, presort, nested) if *presort == Self::presort_name() => typeinfo
+                    .declare_nested_sort(
+                        format!("{name}Output").into(),
+                        *presort,
+                        nested,
+                        span.clone(),
+                    )?,
+                _ => {
+                    return Err(TypeError::DisallowedSort(
+                        name,
+                        "the output must be a sort name or a nested function sort".into(),
+                        span,
+                    ))
+                }
+            };
+
+            let (input_sorts, variadic) = match inputs {
+                Expr::Call(inputs_span, first, rest_args) => {
+                    // Inputs that start with a container sort are parsed with an empty head
+                    let mut all_args: Vec<Expr> = if first.as_str().is_empty() {
+                        rest_args.clone()
+                    } else {
+                        once(Expr::Var(inputs_span.clone(), *first))
+                            .chain(rest_args.iter().cloned())
+                            .collect()
+                    };
+                    let is_ellipsis =
+                        |arg: &Expr| matches!(arg, Expr::Var(_, arg) if *arg == *ELLIPSIS);
+                    // A trailing `...` makes the input before it repeat
+                    let variadic = all_args.last().is_some_and(is_ellipsis);
+                    if variadic {
+                        all_args.pop();
+                    }
+                    if all_args.is_empty() || all_args.iter().any(is_ellipsis) {
+                        return Err(TypeError::DisallowedSort(
+                            name,
+                            "`...` must directly follow the last input sort".into(),
+                            inputs_span.clone(),
+                        ));
+                    }
+                    let mut input_sorts = vec![];
+                    for (i, arg) in all_args.iter().enumerate() {
+                        input_sorts.push(match arg {
+                            Expr::Var(arg_span, arg) => typeinfo
                                 .sorts
                                 .get(arg)
-                                .ok_or(TypeError::UndefinedSort(*arg, span.clone()))
-                                .cloned()
-                        })
-                        .collect::<Result<Vec<_>, _>>()?
+                                .ok_or(TypeError::UndefinedSort(*arg, arg_span.clone()))?
+                                .clone(),
+                            // Declared as `<name>Input<i>`, see `TypeInfo::declare_nested_sort`
+                            Expr::Call(arg_span, presort, nested)
+                                if typeinfo.presorts.contains_key(presort) =>
+                            {
+                                typeinfo.declare_nested_sort(
+                                    format!("{name}Input{i}").into(),
+                                    *presort,
+                                    nested,
+                                    arg_span.clone(),
+                                )?
+                            }
+                            _ => {
+                                return Err(TypeError::DisallowedSort(
+                                    name,
+                                    "each input must be a sort name or a container sort".into(),
+                                    arg.span(),
+                                ))
+                            }
+                        });
+                    }
+                    (input_sorts, variadic)
                 }
                 // an empty list of inputs args is parsed as a unit literal
-                Expr::Lit(_, Literal::Unit) => vec![],
+                Expr::Lit(_, Literal::Unit) => (vec![], false),
                 _ => panic!("function sort must be called with list of input sorts"),
             };
 
             Ok(Arc::new(Self {
                 name,
                 inputs: input_sorts,
-                output: output_sort.clone(),
+                output: output_sort,
+                variadic,
                 functions: Default::default(),
+                dedup_hits: Default::default(),
+                captures_eq: Default::default(),
+                renames: Default::default(),
+                any_renamed: Default::default(),
+                observer: typeinfo.fn_intern_observer.clone(),
             }))
         } else {
             panic!("function sort must be called with list of input args and output sort");
@@ -152,29 +915,162 @@ impl Sort for FunctionSort {
         self.inputs.iter().any(|s| s.is_eq_sort())
     }
 
+    /// The inputs followed by the output. Partial args are values of some of
+    /// the wrapped function's inputs, which need not be among these.
+    fn element_sorts(&self) -> Vec<ArcSort> {
+        self.inputs
+            .iter()
+            .cloned()
+            .chain(once(self.output.clone()))
+            .collect()
+    }
+
+    fn num_interned(&self) -> usize {
+        self.lock_functions().len()
+    }
+
+    fn truncate_to(&self, len: usize) {
+        self.lock_functions().truncate(len);
+    }
+
+    // The methods below can't report an invalid value, so they treat it like a
+    // function without partial args, named after the sort, that never changes.
+
     fn serialized_name(&self, value: &Value) -> Symbol {
-        self.get_value(value).0
+        self.get_value(value)
+            .map_or(self.name, |function| function.0)
     }
 
+    /// The partial args, in the order they were given to `unstable-fn`. They are copied
+    /// out of the interned value, so storing values of this sort while iterating them is fine.
     fn inner_values(&self, value: &Value) -> Vec<(ArcSort, Value)> {
-        let functions = self.functions.lock().unwrap();
-        let input_values = functions.get_index(value.bits as usize).unwrap();
-        input_values.1.clone()
+        self.get_value(value)
+            .map(|function| function.1)
+            .unwrap_or_default()
+    }
+
+    // `is_eq_container_sort` only looks at the declared inputs, but the partial args
+    // can be of any sort, so the fast paths below check what was actually captured.
+    /// Orders by the wrapped function's name, then by the partial args in order, each
+    /// by its sort's name and then its sort's own order. Unlike the interned indices,
+    /// this does not depend on the order the values were created in.
+    fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        let (Some(a), Some(b)) = (self.get_value(a), self.get_value(b)) else {
+            return a.bits.cmp(&b.bits);
+        };
+        a.0.as_str().cmp(b.0.as_str()).then_with(|| {
+            let mut args = a.1.iter().zip(&b.1);
+            args.find_map(|((sort_a, a), (sort_b, b))| {
+                let order = sort_a
+                    .name()
+                    .as_str()
+                    .cmp(sort_b.name().as_str())
+                    .then_with(|| sort_a.compare(a, b));
+                order.is_ne().then_some(order)
+            })
+            .unwrap_or_else(|| a.1.len().cmp(&b.1.len()))
+        })
     }
 
     fn canonicalize(&self, value: &mut Value, unionfind: &UnionFind) -> bool {
-        let ValueFunction(name, inputs) = self.get_value(value);
+        if !self.captures_eq.load(AtomicOrdering::Relaxed)
+            && !self.any_renamed.load(AtomicOrdering::Relaxed)
+        {
+            return false;
+        }
+        let Some(ValueFunction(name, inputs)) = self.get_value(value) else {
+            return false;
+        };
         let mut changed = false;
+        // Keep the partial args in order, since `inner_values` and extraction rely on it
         let mut new_outputs = vec![];
         for (s, mut v) in inputs.into_iter() {
             changed |= s.canonicalize(&mut v, unionfind);
             new_outputs.push((s, v));
         }
-        *value = ValueFunction(name, new_outputs).store(self).unwrap();
+        // The index also changes if the function was renamed
+        let canonical = ValueFunction(name, new_outputs).store(self).unwrap();
+        changed |= canonical.bits != value.bits;
+        *value = canonical;
         changed
     }
 
+    /// The values are loaded and the canonical ones stored under a single acquisition of
+    /// the interning lock each. With the `parallel` feature, the partial args of different
+    /// values are canonicalized in parallel in between. Either way, the canonical values
+    /// are interned in the order of `values`, so the resulting indices are deterministic.
+    fn canonicalize_batch(&self, values: &mut [Value], unionfind: &UnionFind) -> bool {
+        if !self.captures_eq.load(AtomicOrdering::Relaxed)
+            && !self.any_renamed.load(AtomicOrdering::Relaxed)
+        {
+            return false;
+        }
+        let loaded: Vec<Option<ValueFunction>> = {
+            let functions = self.lock_functions();
+            values
+                .iter()
+                .map(|value| functions.get_index(value.bits as usize).cloned())
+                .collect()
+        };
+        let loaded: Vec<_> = loaded
+            .into_iter()
+            .map(|function| {
+                function.map(|ValueFunction(name, args)| ValueFunction(self.renamed(name), args))
+            })
+            .collect();
+        let canonicalize = |function: Option<ValueFunction>| {
+            let ValueFunction(name, mut inputs) = function?;
+            let mut changed = false;
+            for (sort, value) in inputs.iter_mut() {
+                changed |= sort.canonicalize(value, unionfind);
+            }
+            Some((ValueFunction(name, inputs), changed))
+        };
+        #[cfg(feature = "parallel")]
+        let canonical: Vec<_> = {
+            use rayon::prelude::*;
+            loaded.into_par_iter().map(canonicalize).collect()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let canonical: Vec<_> = loaded.into_iter().map(canonicalize).collect();
+
+        let mut any_changed = false;
+        let mut new_functions = vec![];
+        {
+            let mut functions = self.lock_functions();
+            for (value, canonical) in values.iter_mut().zip(canonical) {
+                // Invalid values are left alone, like in `canonicalize`
+                let Some((function, changed)) = canonical else {
+                    continue;
+                };
+                any_changed |= changed;
+                let captured_count = function.1.len();
+                let name = function.0;
+                let (i, is_new) = functions.insert_full(function);
+                if is_new {
+                    new_functions.push((name, captured_count));
+                } else {
+                    self.dedup_hits.fetch_add(1, AtomicOrdering::Relaxed);
+                }
+                // The index also changes if the function was renamed
+                any_changed |= value.bits != i as u64;
+                value.bits = i as u64;
+            }
+        }
+        // Only notify after releasing the lock, as in `store`
+        for (name, captured_count) in new_functions {
+            self.observer.notify(name, captured_count);
+        }
+        any_changed
+    }
+
     fn register_primitives(self: Arc<Self>, typeinfo: &mut TypeInfo) {
+        // Unlike the others, this primitive does not depend on the function sort,
+        // so only register it once
+        let well_typed: Symbol = "fn-well-typed?".into();
+        if !typeinfo.primitives.contains_key(&well_typed) {
+            typeinfo.add_primitive(WellTyped { name: well_typed });
+        }
         typeinfo.add_primitive(Ctor {
             name: "unstable-fn".into(),
             function: self.clone(),
@@ -183,6 +1079,136 @@ impl Sort for FunctionSort {
             name: "unstable-app".into(),
             function: self.clone(),
         });
+        typeinfo.add_primitive(MemoApply {
+            name: "unstable-app-memo".into(),
+            function: self.clone(),
+        });
+        typeinfo.add_primitive(FunctionLt {
+            name: "unstable-fn-lt".into(),
+            function: self.clone(),
+        });
+        typeinfo.add_primitive(FunctionName {
+            name: "unstable-fn-name".into(),
+            function: self.clone(),
+        });
+        typeinfo.add_primitive(Arity {
+            name: "unstable-fn-arity".into(),
+            function: self.clone(),
+        });
+        typeinfo.add_primitive(FewerCaptured {
+            name: "unstable-fn-fewer-captured".into(),
+            function: self.clone(),
+        });
+        typeinfo.add_primitive(CtorOf {
+            name: "unstable-fn-of".into(),
+            function: self.clone(),
+        });
+        for name in ["unstable-fn-rename", "fn-rebind"] {
+            typeinfo.add_primitive(Rename {
+                name: name.into(),
+                function: self.clone(),
+            });
+        }
+        if self.inputs.len() == 1 && !self.variadic {
+            typeinfo.add_primitive(ApplyMemo {
+                name: "apply-memo-into".into(),
+                function: self.clone(),
+            });
+        }
+        let vec_sorts: Vec<Arc<VecSort>> = typeinfo
+            .sorts
+            .values()
+            .filter_map(|sort| sort.clone().as_arc_any().downcast().ok())
+            .collect();
+        for vec_sort in vec_sorts {
+            vec_sort.register_fn_primitives(self.clone(), typeinfo);
+        }
+        let map_sorts: Vec<Arc<MapSort>> = typeinfo
+            .sorts
+            .iter()
+            .filter(|(name, sort)| **name == sort.name())
+            .filter_map(|(_, sort)| sort.clone().as_arc_any().downcast().ok())
+            .collect();
+        for map_sort in map_sorts {
+            map_sort.register_fn_primitives(self.clone(), typeinfo);
+        }
+        if self.inputs.len() == 1 && !self.variadic && self.inputs[0].name() == self.output.name() {
+            typeinfo.add_primitive(Identity {
+                name: "unstable-identity".into(),
+                function: self.clone(),
+            });
+            typeinfo.add_primitive(Identical {
+                name: IDENTITY.into(),
+                sort: self.output.clone(),
+            });
+        }
+        if self.inputs.len() == 1 && !self.variadic {
+            typeinfo.add_primitive(Const {
+                name: "unstable-const".into(),
+                function: self.clone(),
+            });
+            typeinfo.add_primitive(Constant {
+                name: CONSTANT.into(),
+                function: self.clone(),
+            });
+        }
+        if self.inputs.len() >= 2 && !self.variadic {
+            typeinfo.add_primitive(Flipped {
+                name: FLIPPED.into(),
+                function: self.clone(),
+            });
+        }
+        // Register flipping, currying and partial application between this sort and every sort
+        // declared so far, in whichever direction fits. Aliases are skipped, since they
+        // share a sort.
+        let fn_sorts: Vec<Arc<FunctionSort>> = typeinfo
+            .sorts
+            .iter()
+            .filter(|(name, sort)| **name == sort.name())
+            .filter_map(|(_, sort)| sort.clone().as_arc_any().downcast().ok())
+            .collect();
+        for other in fn_sorts {
+            if self.flips_into(&other) {
+                typeinfo.add_primitive(Flip {
+                    name: "unstable-flip".into(),
+                    function: self.clone(),
+                    result: other.clone(),
+                });
+            }
+            if !Arc::ptr_eq(&self, &other) && other.flips_into(&self) {
+                typeinfo.add_primitive(Flip {
+                    name: "unstable-flip".into(),
+                    function: other.clone(),
+                    result: self.clone(),
+                });
+            }
+            if let Some(levels) = self.curried_levels(&other) {
+                self.clone().register_curry(levels, typeinfo);
+            }
+            if let Some(levels) = other.curried_levels(&self) {
+                other.clone().register_curry(levels, typeinfo);
+            }
+            if self.captures_first_into(&other) {
+                typeinfo.add_primitive(Partial {
+                    name: "unstable-fn-partial".into(),
+                    function: self.clone(),
+                    result: other.clone(),
+                });
+            }
+            if !Arc::ptr_eq(&self, &other) && other.captures_first_into(&self) {
+                typeinfo.add_primitive(Partial {
+                    name: "unstable-fn-partial".into(),
+                    function: other,
+                    result: self.clone(),
+                });
+            }
+        }
+    }
+
+    /// `(unstable-fn-eq a b)` succeeds when both are the same function with equal partial args.
+    /// Named like the other function primitives, instead of after the sort.
+    fn eq_primitive_name(&self, _name: Symbol) -> Option<Symbol> {
+        Some("unstable-fn-eq".into())
     }
 
     fn extract_term(
@@ -192,27 +1218,35 @@ impl Sort for FunctionSort {
         extractor: &Extractor,
         termdag: &mut TermDag,
     ) -> Option<(Cost, Term)> {
-        let ValueFunction(name, inputs) = ValueFunction::load(self, &value);
-        let (cost, args) = inputs.into_iter().try_fold(
-            (1usize, vec![termdag.lit(Literal::String(name))]),
-            |(cost, mut args), (sort, value)| {
-                let (new_cost, term) = extractor.find_best(value, termdag, &sort)?;
-                args.push(term);
-                Some((cost.saturating_add(new_cost), args))
-            },
-        )?;
-
-        Some((cost, termdag.app("unstable-fn".into(), args)))
+        // Nested function values are extracted recursively, and their costs are
+        // combined with this one by the extractor. Partial args are interned before the function that holds
+        // them, so cycles should not occur, but a cyclic value yields no term.
+        // The term is the flat `(unstable-fn "name" args...)`, unless the extractor nests
+        // curried values, see `FunctionSort::try_extract_term`.
+        // The term does not name the sort, and aliases of a sort share it, so values of
+        // structurally identical sorts extract to the same term whichever name they were
+        // declared with.
+        extractor.extract_acyclic(self.name, value, || {
+            self.try_extract_term(value, extractor, termdag).ok()
+        })
     }
 }
 
 impl IntoSort for ValueFunction {
     type Sort = FunctionSort;
     fn store(self, sort: &Self::Sort) -> Option<Value> {
-        let mut functions = sort.functions.lock().unwrap();
-        let (i, _) = functions.insert_full(self);
+        let ValueFunction(name, ref args) = self;
+        let captured_count = args.len();
+        sort.note_captured(args);
+        let (i, is_new) = sort.lock_functions().insert_full(self);
+        // Only notify after releasing the lock, so the observer may read this sort
+        if is_new {
+            sort.observer.notify(name, captured_count);
+        } else {
+            sort.dedup_hits.fetch_add(1, AtomicOrdering::Relaxed);
+        }
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: sort.name,
             bits: i as u64,
         })
@@ -223,6 +1257,7 @@ impl FromSort for ValueFunction {
     type Sort = FunctionSort;
     fn load(sort: &Self::Sort, value: &Value) -> Self {
         sort.get_value(value)
+            .unwrap_or_else(|| panic!("{value:?} is not a value of {}", sort.name))
     }
 }
 
@@ -262,44 +1297,30 @@ impl TypeConstraint for FunctionCTorTypeConstraint {
             if let Some(func_type) = typeinfo.func_types.get(name) {
                 // The arguments contains the return sort as well as the function name
                 let n_partial_args = arguments.len() - 2;
-                // the number of partial args must match the number of inputs from the func type minus the number from
-                // this function sort
-                if self.function.inputs.len() + n_partial_args != func_type.input.len() {
-                    return vec![constraint::impossible(
-                        constraint::ImpossibleConstraint::ArityMismatch {
-                            atom: core::Atom {
-                                span: self.span.clone(),
-                                head: self.name,
-                                args: arguments.to_vec(),
+                match self.function.check_signature(func_type, n_partial_args) {
+                    Ok(()) => {}
+                    Err(SignatureMismatch::Arity) => {
+                        return vec![constraint::impossible(
+                            constraint::ImpossibleConstraint::ArityMismatch {
+                                atom: core::Atom {
+                                    span: self.span.clone(),
+                                    head: self.name,
+                                    args: arguments.to_vec(),
+                                },
+                                expected: self.function.inputs.len() + func_type.input.len() + 1,
                             },
-                            expected: self.function.inputs.len() + func_type.input.len() + 1,
-                        },
-                    )];
-                }
-                // the output type and input types (starting after the partial args) must match between these functions
-                let expected_output = self.function.output.clone();
-                let expected_input = self.function.inputs.clone();
-                let actual_output = func_type.output.clone();
-                let actual_input: Vec<ArcSort> = func_type
-                    .input
-                    .iter()
-                    .skip(n_partial_args)
-                    .cloned()
-                    .collect();
-                if expected_output.name() != actual_output.name()
-                    || expected_input
-                        .iter()
-                        .map(|s| s.name())
-                        .ne(actual_input.iter().map(|s| s.name()))
-                {
-                    return vec![constraint::impossible(
-                        constraint::ImpossibleConstraint::FunctionMismatch {
-                            expected_output,
-                            expected_input,
-                            actual_output,
-                            actual_input,
-                        },
-                    )];
+                        )];
+                    }
+                    Err(SignatureMismatch::Sorts) => {
+                        return vec![constraint::impossible(
+                            constraint::ImpossibleConstraint::FunctionMismatch {
+                                expected_output: self.function.output.clone(),
+                                expected_input: self.function.inputs.clone(),
+                                actual_output: func_type.output.clone(),
+                                actual_input: func_type.input[n_partial_args..].to_vec(),
+                            },
+                        )];
+                    }
                 }
                 // if they match, then just make sure the partial args match as well
                 return func_type
@@ -313,6 +1334,17 @@ impl TypeConstraint for FunctionCTorTypeConstraint {
                     .chain(once(output_sort_constraint))
                     .collect();
             }
+            if typeinfo.strict_fn_names && !typeinfo.primitives.contains_key(name) {
+                return vec![constraint::impossible(
+                    constraint::ImpossibleConstraint::UnknownFunction {
+                        name: *name,
+                        span: self.span.clone(),
+                    },
+                )];
+            }
+            if let Some(primitives) = typeinfo.primitives.get(name) {
+                return self.primitive_constraints(primitives, arguments, typeinfo);
+            }
         }
 
         // Otherwise we just try assuming it's this function, we don't know if it is or not
@@ -323,6 +1355,55 @@ impl TypeConstraint for FunctionCTorTypeConstraint {
     }
 }
 
+impl FunctionCTorTypeConstraint {
+    /// Requires one of `primitives` to accept the partial args followed by one arg of each
+    /// input of the function sort, like when the value is applied. The args and output of
+    /// that call are fresh variables named after this call's output.
+    fn primitive_constraints(
+        &self,
+        primitives: &[Primitive],
+        arguments: &[AtomTerm],
+        typeinfo: &TypeInfo,
+    ) -> Vec<Box<dyn Constraint<AtomTerm, ArcSort>>> {
+        let (output, partial_args) = arguments[1..].split_last().unwrap();
+        let fresh: Vec<(AtomTerm, ArcSort)> = self
+            .function
+            .inputs
+            .iter()
+            .chain(once(&self.function.output))
+            .enumerate()
+            .map(|(i, sort)| {
+                let var = format!("{output}__{}_arg{i}", self.function.name);
+                (AtomTerm::Var(self.span.clone(), var.into()), sort.clone())
+            })
+            .collect();
+        let call_args: Vec<AtomTerm> = partial_args
+            .iter()
+            .cloned()
+            .chain(fresh.iter().map(|(var, _)| var.clone()))
+            .collect();
+        let alternatives = primitives
+            .iter()
+            .map(|primitive| {
+                constraint::and(
+                    primitive
+                        .get_type_constraints(&self.span)
+                        .get(&call_args, typeinfo),
+                )
+            })
+            .collect();
+        fresh
+            .into_iter()
+            .map(|(var, sort)| constraint::assign(var, sort))
+            .chain([
+                constraint::assign(arguments[0].clone(), Arc::new(StringSort) as ArcSort),
+                constraint::assign(output.clone(), self.function.clone() as ArcSort),
+                constraint::xor(alternatives),
+            ])
+            .collect()
+    }
+}
+
 // (unstable-fn "name" [<arg1>, <arg2>, ...])
 struct Ctor {
     name: Symbol,
@@ -348,9 +1429,11 @@ impl PrimitiveLike for Ctor {
         sorts: (&[ArcSort], &ArcSort),
         _egraph: Option<&mut EGraph>,
     ) -> Option<Value> {
+        // Callers outside the typechecker may pass mismatched values and sorts
+        if values.is_empty() || values.len() != sorts.0.len() {
+            return None;
+        }
         let name = Symbol::load(&StringSort, &values[0]);
-
-        assert!(values.len() == sorts.0.len());
         let args: Vec<(ArcSort, Value)> = values[1..]
             .iter()
             .zip(&sorts.0[1..])
@@ -373,34 +1456,1094 @@ impl PrimitiveLike for Apply {
     }
 
     fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
-        let mut sorts: Vec<ArcSort> = vec![self.function.clone()];
-        sorts.extend(self.function.inputs.clone());
-        sorts.push(self.function.output.clone());
-        SimpleTypeConstraint::new(self.name(), sorts, span.clone()).into_box()
-    }
-
-    fn apply(
+        Box::new(ApplyTypeConstraint {
+            name: self.name,
+            function: self.function.clone(),
+            span: span.clone(),
+        })
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph = egraph.expect("`unstable-app` is not supported yet in facts.");
+        let (function, args) = values.split_first()?;
+        self.function.apply(function, args, egraph).ok()
+    }
+}
+
+// (unstable-app-memo <function> <arg>...) is like unstable-app, but reuses the result of
+// an earlier application to the same args until the e-graph is next rebuilt with changes,
+// so it should only be used with functions whose result depends on nothing else.
+struct MemoApply {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for MemoApply {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        Box::new(ApplyTypeConstraint {
+            name: self.name,
+            function: self.function.clone(),
+            span: span.clone(),
+        })
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let (function, args) = values.split_first()?;
+        let (name, partial_args) = self.function.get_value(function)?.hashable_owned();
+        let key = (self.function.name, name, partial_args, args.to_vec());
+        if let Some(output) = egraph.fn_memo.get(&key) {
+            return Some(*output);
+        }
+        // Failed applications are not memoized, so they are retried
+        let output = self.function.apply(function, args, egraph).ok()?;
+        egraph.fn_memo.insert(key, output);
+        Some(output)
+    }
+}
+
+/// Takes a function followed by its args and returns its output, where a variadic
+/// function may repeat its last input any number of times
+struct ApplyTypeConstraint {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    span: Span,
+}
+
+impl TypeConstraint for ApplyTypeConstraint {
+    fn get(
+        &self,
+        arguments: &[AtomTerm],
+        _typeinfo: &TypeInfo,
+    ) -> Vec<Box<dyn Constraint<AtomTerm, ArcSort>>> {
+        // The arguments contain the function as well as the return value
+        let arg_sorts = arguments
+            .len()
+            .checked_sub(2)
+            .and_then(|n_args| self.function.arg_sorts(n_args));
+        let Some(arg_sorts) = arg_sorts else {
+            return vec![constraint::impossible(
+                constraint::ImpossibleConstraint::ArityMismatch {
+                    atom: core::Atom {
+                        span: self.span.clone(),
+                        head: self.name,
+                        args: arguments.to_vec(),
+                    },
+                    expected: self.function.min_arity() + 2,
+                },
+            )];
+        };
+        let sorts = once(self.function.clone() as ArcSort)
+            .chain(arg_sorts)
+            .chain(once(self.function.output.clone()));
+        arguments
+            .iter()
+            .cloned()
+            .zip(sorts)
+            .map(|(arg, sort)| constraint::assign(arg, sort))
+            .collect()
+    }
+}
+
+// (apply-memo-into <function> <arg> <table name>) returns the output of the table for the
+// arg, calling the function and inserting its result into the table if there is none, so
+// the function runs once for each e-class.
+// Fails if the table does not map the function's input sort to its output sort.
+struct ApplyMemo {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for ApplyMemo {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.function.clone(),
+                self.function.inputs[0].clone(),
+                Arc::new(StringSort),
+                self.function.output.clone(),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let table = Symbol::load(&StringSort, &values[2]);
+        let input = &self.function.inputs[0];
+        let schema = &egraph.functions.get(&table)?.schema;
+        if schema.input.len() != 1
+            || schema.input[0].name() != input.name()
+            || schema.output.name() != self.function.output.name()
+        {
+            return None;
+        }
+
+        let arg = egraph.find(input, values[1]);
+        if let Some(output) = egraph.functions[&table].get(&[arg]) {
+            return Some(output);
+        }
+        let output = self.function.apply(&values[0], &[arg], egraph).ok()?;
+        let timestamp = egraph.timestamp;
+        let function = egraph.functions.get_mut(&table)?;
+        // The call itself may have added a row for the arg, which is then kept
+        if let Some(existing) = function.get(&[arg]) {
+            return Some(existing);
+        }
+        function.insert(&[arg], output, timestamp);
+        Some(output)
+    }
+}
+
+/// The primitive that flipped functions wrap, see [`Flipped`].
+const FLIPPED: &str = "unstable-flipped";
+
+// (unstable-flip <function>) returns a function taking the first two inputs of <function> in
+// the opposite order, given a sort for it. It wraps `unstable-flipped` with <function> as
+// its partial arg.
+struct Flip {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    result: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Flip {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.function.clone(), self.result.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let function = self.function.clone() as ArcSort;
+        ValueFunction(FLIPPED.into(), vec![(function, values[0])]).store(&self.result)
+    }
+}
+
+// (unstable-flipped <function> <b> <a> <rest>...) applies <function> to <a> <b> <rest>...
+struct Flipped {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Flipped {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        let inputs = &self.function.inputs;
+        let sorts = once(self.function.clone() as ArcSort)
+            .chain([inputs[1].clone(), inputs[0].clone()])
+            .chain(inputs[2..].iter().cloned())
+            .chain(once(self.function.output.clone()))
+            .collect();
+        SimpleTypeConstraint::new(self.name(), sorts, span.clone()).into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let [function, b, a, rest @ ..] = values else {
+            return None;
+        };
+        let args: Vec<Value> = [*a, *b].into_iter().chain(rest.iter().copied()).collect();
+        self.function.apply(function, &args, egraph).ok()
+    }
+}
+
+// (unstable-fn-partial <function> <arg>) returns the function with <arg> appended to its
+// partial args, as a value of the sort taking the remaining inputs, which must be declared
+struct Partial {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    result: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Partial {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.function.clone(),
+                self.function.inputs[0].clone(),
+                self.result.clone(),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let ValueFunction(name, mut args) = self.function.get_value(&values[0])?;
+        args.push((self.function.inputs[0].clone(), values[1]));
+        ValueFunction(name, args).store(&self.result)
+    }
+}
+
+/// The primitive that identity functions wrap, see [`Identical`].
+const IDENTITY: &str = "unstable-id";
+
+// (unstable-identity) returns the function of a sort like (UnstableFn (A) A) that returns
+// its arg, which wraps `unstable-id`
+struct Identity {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Identity {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(self.name(), vec![self.function.clone()], span.clone()).into_box()
+    }
+
+    fn apply(
+        &self,
+        _values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        ValueFunction(IDENTITY.into(), vec![]).store(&self.function)
+    }
+}
+
+// (unstable-id x) returns x. `FunctionSort::apply` returns the arg of an identity function
+// itself, so this is only called when the function was given its arg as a partial arg.
+struct Identical {
+    name: Symbol,
+    sort: ArcSort,
+}
+
+impl PrimitiveLike for Identical {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.sort.clone(), self.sort.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        Some(values[0])
+    }
+}
+
+/// The primitive that constant functions wrap, see [`Constant`].
+const CONSTANT: &str = "unstable-constant";
+
+// (unstable-const c) returns a function of one input that ignores its arg and returns c.
+// It wraps `unstable-constant` with c as its partial arg.
+struct Const {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Const {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.function.output.clone(), self.function.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let output = self.function.output.clone();
+        ValueFunction(CONSTANT.into(), vec![(output, values[0])]).store(&self.function)
+    }
+}
+
+// (unstable-constant c x) returns c
+struct Constant {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Constant {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        let output = self.function.output.clone();
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![output.clone(), self.function.inputs[0].clone(), output],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        Some(values[0])
+    }
+}
+
+/// The primitive that curried functions wrap, see [`Curried`].
+const CURRIED: &str = "unstable-curried";
+
+// (unstable-curry <function>) returns a function taking the first input of <function> and
+// returning a function taking the next one, and so on, until the last returns the output,
+// like (UnstableFn (A) (UnstableFn (B) C)) for (UnstableFn (A B) C).
+// It wraps `unstable-curried` with <function> as its partial arg.
+struct Curry {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    result: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Curry {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.function.clone(), self.result.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let function = self.function.clone() as ArcSort;
+        ValueFunction(CURRIED.into(), vec![(function, values[0])]).store(&self.result)
+    }
+}
+
+// (unstable-curried <function> <arg1> ... <argk>) returns the curried function taking the
+// next input with all k args as partial args, or applies <function> once all are given.
+struct Curried {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    /// The number of args given so far, including the one this is applied to.
+    given: usize,
+    /// The sort of the curried function taking the next input, unless all were given.
+    next: Option<Arc<FunctionSort>>,
+}
+
+impl PrimitiveLike for Curried {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        let output = match &self.next {
+            Some(next) => next.clone() as ArcSort,
+            None => self.function.output.clone(),
+        };
+        let sorts = once(self.function.clone() as ArcSort)
+            .chain(self.function.inputs[..self.given].iter().cloned())
+            .chain(once(output))
+            .collect();
+        SimpleTypeConstraint::new(self.name(), sorts, span.clone()).into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let Some(next) = &self.next else {
+            let egraph =
+                egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+            return self.function.apply(&values[0], &values[1..], egraph).ok();
+        };
+        let args = once(self.function.clone() as ArcSort)
+            .chain(self.function.inputs[..self.given].iter().cloned())
+            .zip(values.iter().copied())
+            .collect();
+        ValueFunction(CURRIED.into(), args).store(next)
+    }
+}
+
+// (unstable-fn-lt <a> <b>) succeeds if a comes before b in the order of `FunctionSort::compare`,
+// by name and then by partial args, which does not depend on when the values were created
+struct FunctionLt {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for FunctionLt {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.function.clone(),
+                self.function.clone(),
+                Arc::new(UnitSort),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        self.function
+            .compare(&values[0], &values[1])
+            .is_lt()
+            .then(Value::unit)
+    }
+}
+
+// (unstable-fn-name <function>) returns the name of the function, without any partial args
+struct FunctionName {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for FunctionName {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.function.clone(), Arc::new(StringSort)],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let ValueFunction(name, _) = self.function.get_value(&values[0])?;
+        name.store(&StringSort)
+    }
+}
+
+// (unstable-fn-arity <function>) returns the number of args left to apply. Partial args count
+// against the wrapped function's own inputs, so this is the same for every value of a sort,
+// and for a variadic sort it leaves out the repeated input.
+struct Arity {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Arity {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.function.clone(), Arc::new(I64Sort)],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        _values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        // The partial args were checked against the function when the value was created,
+        // so whatever is left over is exactly the inputs of this sort
+        (self.function.min_arity() as i64).store(&I64Sort)
+    }
+}
+
+// (unstable-fn-fewer-captured <a> <b>) returns whichever function has fewer partial args,
+// or `a` if they have as many, e.g. to merge function values in a table
+struct FewerCaptured {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for FewerCaptured {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        AllEqualTypeConstraint::new(self.name(), span.clone())
+            .with_all_arguments_sort(self.function.clone())
+            .with_exact_length(3)
+            .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let ValueFunction(_, a) = self.function.get_value(&values[0])?;
+        let ValueFunction(_, b) = self.function.get_value(&values[1])?;
+        Some(if b.len() < a.len() {
+            values[1]
+        } else {
+            values[0]
+        })
+    }
+}
+
+// (unstable-fn-rename <function> "name") replaces the wrapped function, keeping the partial args.
+// `name` must take the same args and return the same output. Also registered as `fn-rebind`.
+struct Rename {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Rename {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        Box::new(RenameTypeConstraint {
+            name: self.name,
+            function: self.function.clone(),
+            span: span.clone(),
+        })
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let ValueFunction(_, args) = self.function.get_value(&values[0])?;
+        let name = Symbol::load(&StringSort, &values[1]);
+        // The partial args are only known now, so check that they fit the new function
+        if let Some(egraph) = egraph {
+            let typeinfo = egraph.type_info();
+            let types: Vec<ArcSort> = args
+                .iter()
+                .map(|(sort, _)| sort.clone())
+                .chain(self.function.inputs.iter().cloned())
+                .chain(once(self.function.output.clone()))
+                .collect();
+            let compatible = if let Some(func_type) = typeinfo.func_types.get(&name) {
+                self.function.signature_matches(name, args.len(), typeinfo)
+                    && args
+                        .iter()
+                        .zip(&func_type.input)
+                        .all(|((sort, _), input)| sort.name() == input.name())
+            } else {
+                typeinfo
+                    .primitives
+                    .get(&name)
+                    .is_some_and(|primitives| primitives.iter().any(|p| p.accept(&types, typeinfo)))
+            };
+            if !compatible {
+                return None;
+            }
+        }
+        ValueFunction(name, args).store(&self.function)
+    }
+}
+
+/// Like [`FunctionCTorTypeConstraint`], checks a literal function name against the
+/// function sort, though only the inputs after the partial args and the output can be
+/// checked before the partial args are known.
+struct RenameTypeConstraint {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    span: Span,
+}
+
+impl TypeConstraint for RenameTypeConstraint {
+    fn get(
+        &self,
+        arguments: &[AtomTerm],
+        typeinfo: &TypeInfo,
+    ) -> Vec<Box<dyn Constraint<AtomTerm, ArcSort>>> {
+        if arguments.len() != 3 {
+            return vec![constraint::impossible(
+                constraint::ImpossibleConstraint::ArityMismatch {
+                    atom: core::Atom {
+                        span: self.span.clone(),
+                        head: self.name,
+                        args: arguments.to_vec(),
+                    },
+                    expected: 3,
+                },
+            )];
+        }
+        if let AtomTerm::Literal(_, Literal::String(ref name)) = arguments[1] {
+            if let Some(func_type) = typeinfo.func_types.get(name) {
+                let compatible = func_type
+                    .input
+                    .len()
+                    .checked_sub(self.function.inputs.len())
+                    .is_some_and(|captured_count| {
+                        self.function
+                            .check_signature(func_type, captured_count)
+                            .is_ok()
+                    });
+                if !compatible {
+                    let n_inputs = func_type.input.len().min(self.function.inputs.len());
+                    return vec![constraint::impossible(
+                        constraint::ImpossibleConstraint::FunctionMismatch {
+                            expected_output: self.function.output.clone(),
+                            expected_input: self.function.inputs.clone(),
+                            actual_output: func_type.output.clone(),
+                            actual_input: func_type.input[func_type.input.len() - n_inputs..]
+                                .to_vec(),
+                        },
+                    )];
+                }
+            } else if typeinfo.strict_fn_names && !typeinfo.primitives.contains_key(name) {
+                return vec![constraint::impossible(
+                    constraint::ImpossibleConstraint::UnknownFunction {
+                        name: *name,
+                        span: self.span.clone(),
+                    },
+                )];
+            }
+        }
+        vec![
+            constraint::assign(arguments[0].clone(), self.function.clone() as ArcSort),
+            constraint::assign(arguments[1].clone(), Arc::new(StringSort) as ArcSort),
+            constraint::assign(arguments[2].clone(), self.function.clone() as ArcSort),
+        ]
+    }
+}
+
+// (unstable-fn-of "Ctor") wraps a constructor in a function value, without partial args,
+// so it must take the inputs of the function sort and return its output
+struct CtorOf {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for CtorOf {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        Box::new(CtorOfTypeConstraint {
+            name: self.name,
+            function: self.function.clone(),
+            span: span.clone(),
+        })
+    }
+
+    fn apply(
         &self,
         values: &[Value],
         _sorts: (&[ArcSort], &ArcSort),
         egraph: Option<&mut EGraph>,
     ) -> Option<Value> {
-        let egraph = egraph.expect("`unstable-app` is not supported yet in facts.");
-        Some(self.function.apply(&values[0], &values[1..], egraph))
+        let name = Symbol::load(&StringSort, &values[0]);
+        // Only a literal name is checked when typechecking
+        if let Some(egraph) = egraph {
+            let typeinfo = egraph.type_info();
+            let is_ctor = typeinfo
+                .func_types
+                .get(&name)
+                .is_some_and(|func_type| func_type.subtype == FunctionSubtype::Constructor);
+            if !is_ctor || !self.function.signature_matches(name, 0, typeinfo) {
+                return None;
+            }
+        }
+        ValueFunction(name, vec![]).store(&self.function)
+    }
+}
+
+// (unstable-fn-table "name" rows) makes a function value for each row of partial args in a
+// (Vec (Vec A)), returned as a vec of function values.
+struct Table {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    rows: Arc<VecSort>,
+    row: Arc<VecSort>,
+    result: Arc<VecSort>,
+}
+
+impl PrimitiveLike for Table {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        Box::new(TableTypeConstraint {
+            name: self.name,
+            function: self.function.clone(),
+            arg: self.row.element(),
+            rows: self.rows.clone(),
+            result: self.result.clone(),
+            span: span.clone(),
+        })
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let name = Symbol::load(&StringSort, &values[0]);
+        let arg = self.row.element();
+        let rows: Vec<Vec<Value>> = Vec::load(&self.rows, &values[1])
+            .iter()
+            .map(|row| Vec::load(&self.row, row))
+            .collect();
+        // Only a literal name is checked when typechecking
+        if let Some(egraph) = egraph {
+            let func_type = egraph.type_info().func_types.get(&name)?;
+            let captured_count = self.function.table_captured_count(func_type, &arg)?;
+            if rows.iter().any(|row| row.len() != captured_count) {
+                return None;
+            }
+        }
+        let functions = rows
+            .into_iter()
+            .map(|row| ValueFunction(name, row.into_iter().map(|v| (arg.clone(), v)).collect()))
+            .collect();
+        self.function.store_all(functions).store(&self.result)
+    }
+}
+
+/// Requires a literal name to be a declared function that takes partial args of the
+/// row sort before the inputs of the function sort.
+struct TableTypeConstraint {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    arg: ArcSort,
+    rows: Arc<VecSort>,
+    result: Arc<VecSort>,
+    span: Span,
+}
+
+impl TypeConstraint for TableTypeConstraint {
+    fn get(
+        &self,
+        arguments: &[AtomTerm],
+        typeinfo: &TypeInfo,
+    ) -> Vec<Box<dyn Constraint<AtomTerm, ArcSort>>> {
+        if arguments.len() != 3 {
+            return vec![constraint::impossible(
+                constraint::ImpossibleConstraint::ArityMismatch {
+                    atom: core::Atom {
+                        span: self.span.clone(),
+                        head: self.name,
+                        args: arguments.to_vec(),
+                    },
+                    expected: 3,
+                },
+            )];
+        }
+        if let AtomTerm::Literal(_, Literal::String(ref name)) = arguments[0] {
+            let Some(func_type) = typeinfo.func_types.get(name) else {
+                return vec![constraint::impossible(
+                    constraint::ImpossibleConstraint::UnknownFunction {
+                        name: *name,
+                        span: self.span.clone(),
+                    },
+                )];
+            };
+            if self
+                .function
+                .table_captured_count(func_type, &self.arg)
+                .is_none()
+            {
+                return vec![constraint::impossible(
+                    constraint::ImpossibleConstraint::FunctionMismatch {
+                        expected_output: self.function.output.clone(),
+                        expected_input: self.function.inputs.clone(),
+                        actual_output: func_type.output.clone(),
+                        actual_input: func_type.input.clone(),
+                    },
+                )];
+            }
+        }
+        vec![
+            constraint::assign(arguments[0].clone(), Arc::new(StringSort) as ArcSort),
+            constraint::assign(arguments[1].clone(), self.rows.clone() as ArcSort),
+            constraint::assign(arguments[2].clone(), self.result.clone() as ArcSort),
+        ]
+    }
+}
+
+/// Requires a literal name to be a constructor with the inputs and output of the
+/// function sort.
+struct CtorOfTypeConstraint {
+    name: Symbol,
+    function: Arc<FunctionSort>,
+    span: Span,
+}
+
+impl TypeConstraint for CtorOfTypeConstraint {
+    fn get(
+        &self,
+        arguments: &[AtomTerm],
+        typeinfo: &TypeInfo,
+    ) -> Vec<Box<dyn Constraint<AtomTerm, ArcSort>>> {
+        if arguments.len() != 2 {
+            return vec![constraint::impossible(
+                constraint::ImpossibleConstraint::ArityMismatch {
+                    atom: core::Atom {
+                        span: self.span.clone(),
+                        head: self.name,
+                        args: arguments.to_vec(),
+                    },
+                    expected: 2,
+                },
+            )];
+        }
+        if let AtomTerm::Literal(_, Literal::String(ref name)) = arguments[0] {
+            match typeinfo.func_types.get(name) {
+                Some(func_type) if func_type.subtype == FunctionSubtype::Constructor => {
+                    if self.function.check_signature(func_type, 0).is_err() {
+                        return vec![constraint::impossible(
+                            constraint::ImpossibleConstraint::FunctionMismatch {
+                                expected_output: self.function.output.clone(),
+                                expected_input: self.function.inputs.clone(),
+                                actual_output: func_type.output.clone(),
+                                actual_input: func_type.input.clone(),
+                            },
+                        )];
+                    }
+                }
+                Some(_) => {
+                    return vec![constraint::impossible(
+                        constraint::ImpossibleConstraint::NotConstructor {
+                            name: *name,
+                            span: self.span.clone(),
+                        },
+                    )];
+                }
+                None => {
+                    return vec![constraint::impossible(
+                        constraint::ImpossibleConstraint::UnknownFunction {
+                            name: *name,
+                            span: self.span.clone(),
+                        },
+                    )];
+                }
+            }
+        }
+        vec![
+            constraint::assign(arguments[0].clone(), Arc::new(StringSort) as ArcSort),
+            constraint::assign(arguments[1].clone(), self.function.clone() as ArcSort),
+        ]
+    }
+}
+
+// (fn-well-typed? "name") is true if the declared function `name` could be
+// made into a value of some function sort without partially applying any args
+struct WellTyped {
+    name: Symbol,
+}
+
+impl PrimitiveLike for WellTyped {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![Arc::new(StringSort), Arc::new(BoolSort)],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let name = Symbol::load(&StringSort, &values[0]);
+        let typeinfo = &egraph.type_info;
+        let matches = typeinfo.sorts.values().any(|sort| {
+            sort.clone()
+                .as_arc_any()
+                .downcast::<FunctionSort>()
+                .is_ok_and(|function| function.signature_matches(name, 0, typeinfo))
+        });
+        matches.store(&BoolSort)
     }
 }
 
+/// How a call through a function value runs, cached by the function name and sorts.
+#[derive(Clone)]
+pub(crate) enum CompiledCall {
+    /// A primitive that takes and returns no eqsort values, applied directly.
+    Primitive(Arc<SpecializedPrimitive>),
+    /// A program run on the action stack.
+    Program(Arc<Program>),
+}
+
 /// Call function (either primitive or eqsort) <name> with value args <args> and return the value.
 ///
 /// Does this in a similar way to how merge functions are resolved, using the stack and actions,
-/// so that we can re-use the logic for primitive and regular functions.
-fn call_fn(egraph: &mut EGraph, name: &Symbol, types: Vec<ArcSort>, args: Vec<Value>) -> Value {
-    // Make a call with temp vars as each of the args
+/// so that we can re-use the logic for primitive and regular functions. Primitives over sorts
+/// without e-classes skip the actions and are applied directly.
+fn call_fn(
+    egraph: &mut EGraph,
+    name: &Symbol,
+    types: Vec<ArcSort>,
+    args: Vec<Value>,
+) -> Result<Value, Error> {
+    // The caller must not hold an interning lock here, since the call may store function
+    // values of any sort, see `FunctionSort::functions`.
+    // Compiling the call is much slower than running it, so reuse the program for
+    // calls with the same signature. Function signatures can't change without a
+    // `pop`, which also restores the cache from before the matching `push`.
+    let key = (*name, types.iter().map(|sort| sort.name()).collect());
+    let call = match egraph.fn_programs.get(&key) {
+        Some(call) => call.clone(),
+        None => {
+            let call = compile_call(egraph, name, types, args.len())?;
+            egraph.fn_programs.insert(key, call.clone());
+            call
+        }
+    };
+    match call {
+        CompiledCall::Primitive(p) => p
+            .primitive
+            .apply(&args, (&p.input, &p.output), Some(egraph))
+            .ok_or_else(|| {
+                let err = Error::PrimitiveError(p.primitive.clone(), args.clone());
+                Error::CallError(*name, args, Box::new(err))
+            }),
+        CompiledCall::Program(program) => {
+            // Similar to how the `MergeFn::Expr` case is handled in `Egraph::perform_set`
+            // egraph.rebuild().unwrap();
+            let mut stack = vec![];
+            egraph
+                .run_actions(&mut stack, &args, &program)
+                .map_err(|err| Error::CallError(*name, args, Box::new(err)))?;
+            stack.pop().ok_or(Error::NoCallResult(*name))
+        }
+    }
+}
+
+/// Compile a call to <name> taking the first `n_args` of `types` as arguments, bound to
+/// `__arg_0`, `__arg_1`, ... in order, unless it can be applied directly.
+fn compile_call(
+    egraph: &mut EGraph,
+    name: &Symbol,
+    types: Vec<ArcSort>,
+    n_args: usize,
+) -> Result<CompiledCall, Error> {
+    // Make a call with temp vars as each of the args.
+    // These are never global refs: a global captured by a function value was already
+    // evaluated when the value was created, so the call only ever sees plain values.
     let resolved_call = ResolvedCall::from_resolution(name, types.as_slice(), &egraph.type_info);
+    if let ResolvedCall::Primitive(primitive) = &resolved_call {
+        if types.iter().all(|sort| !sort.is_eq_sort()) {
+            return Ok(CompiledCall::Primitive(Arc::new(primitive.clone())));
+        }
+    }
     let arg_vars: Vec<_> = types
         .into_iter()
         // Skip last sort which is the output sort
-        .take(args.len())
+        .take(n_args)
         .enumerate()
         .map(|(i, sort)| ResolvedVar {
             name: format!("__arg_{}", i).into(),
@@ -412,18 +2555,793 @@ fn call_fn(egraph: &mut EGraph, name: &Symbol, types: Vec<ArcSort>, args: Vec<Va
     let resolved_args = arg_vars.into_iter().map(|v| var!(v));
     let expr = call!(resolved_call, resolved_args);
     // Similar to how the merge function is created in `Function::new`
-    let (actions, mapped_expr) = expr
-        .to_core_actions(
-            &egraph.type_info,
-            &mut binding.clone(),
-            &mut egraph.parser.symbol_gen,
+    let (actions, mapped_expr) = expr.to_core_actions(
+        &egraph.type_info,
+        &mut binding.clone(),
+        &mut egraph.parser.symbol_gen,
+    )?;
+    let target = mapped_expr.get_corresponding_var_or_lit(&egraph.type_info);
+    let program = egraph
+        .compile_expr(&binding, &actions, &target)
+        .map_err(Error::TypeErrors)?;
+    Ok(CompiledCall::Program(Arc::new(program)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int_fn_sort(egraph: &mut EGraph) -> Arc<FunctionSort> {
+        egraph
+            .parse_and_run_program(None, "(sort IntFn (UnstableFn (i64) i64))")
+            .unwrap();
+        egraph.get_sort::<FunctionSort>().unwrap()
+    }
+
+    #[test]
+    fn test_ctor_mismatched_arity() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        let ctor = Ctor {
+            name: "unstable-fn".into(),
+            function: function.clone(),
+        };
+        let plus = Symbol::from("+").store(&StringSort).unwrap();
+        let two = 2i64.store(&I64Sort).unwrap();
+        let string: ArcSort = Arc::new(StringSort);
+        let int: ArcSort = Arc::new(I64Sort);
+        let output: ArcSort = function.clone();
+
+        assert!(ctor.apply(&[], (&[], &output), None).is_none());
+        assert!(ctor
+            .apply(&[plus, two], (&[string.clone()], &output), None)
+            .is_none());
+        assert!(ctor
+            .apply(&[plus, two], (&[string, int], &output), None)
+            .is_some());
+    }
+
+    #[test]
+    fn test_inner_values_order() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                "(datatype Math (Var String) (Mix Math i64 String Math Math))
+                 (sort MathFn (UnstableFn (Math) Math))
+                 (let a (Var \"a\"))
+                 (let c (Var \"c\"))
+                 (let b (Var \"b\"))
+                 (let f (unstable-fn \"Mix\" b 1 \"s\" a))",
+            )
+            .unwrap();
+        let function = egraph.get_sort::<FunctionSort>().unwrap();
+        let mut eval = |name: &str| {
+            egraph
+                .eval_expr(&Expr::Var(span!(), name.into()))
+                .unwrap()
+                .1
+        };
+        let (a, b, mut f) = (eval("a"), eval("b"), eval("f"));
+        let inner = |f: &Value| -> Vec<(Symbol, Value)> {
+            function
+                .inner_values(f)
+                .into_iter()
+                .map(|(sort, value)| (sort.name(), value))
+                .collect()
+        };
+        let expected = |b: Value| {
+            vec![
+                ("Math".into(), b),
+                ("i64".into(), 1i64.store(&I64Sort).unwrap()),
+                (
+                    "String".into(),
+                    Symbol::from("s").store(&StringSort).unwrap(),
+                ),
+                ("Math".into(), a),
+            ]
+        };
+        assert_eq!(inner(&f), expected(b));
+
+        // Canonicalizing after `b` is merged away keeps the args in place
+        egraph.parse_and_run_program(None, "(union c b)").unwrap();
+        let mut new_b = b;
+        let unionfind = &egraph.unionfind;
+        function.inputs[0].canonicalize(&mut new_b, unionfind);
+        assert_ne!(new_b, b);
+        function.canonicalize(&mut f, unionfind);
+        assert_eq!(inner(&f), expected(new_b));
+    }
+
+    #[test]
+    fn test_apply_mismatched_arity() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        let (_, add_two) = egraph
+            .eval_expr(&Expr::Call(
+                span!(),
+                "unstable-fn".into(),
+                vec![
+                    Expr::Lit(span!(), Literal::String("+".into())),
+                    Expr::Lit(span!(), Literal::Int(2)),
+                ],
+            ))
+            .unwrap();
+        let apply = Apply {
+            name: "unstable-app".into(),
+            function: function.clone(),
+        };
+        let three = 3i64.store(&I64Sort).unwrap();
+        let sorts: (&[ArcSort], &ArcSort) = (&[], &function.output);
+
+        assert!(apply.apply(&[], sorts, Some(&mut egraph)).is_none());
+        assert!(apply.apply(&[add_two], sorts, Some(&mut egraph)).is_none());
+        assert!(apply
+            .apply(&[add_two, three, three], sorts, Some(&mut egraph))
+            .is_none());
+        let five = apply.apply(&[add_two, three], sorts, Some(&mut egraph));
+        assert_eq!(five.map(|v| i64::load(&I64Sort, &v)), Some(5));
+    }
+
+    #[test]
+    fn test_extract_nested_function_values() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (sort IntFn (UnstableFn (i64) i64))
+                (function twice (IntFn i64) i64 :no-merge)
+                (let f (unstable-fn "twice" (unstable-fn "twice" (unstable-fn "+" 1))))
+                "#,
+            )
+            .unwrap();
+        let (sort, f) = egraph.eval_expr(&Expr::Var(span!(), "f".into())).unwrap();
+        let mut termdag = TermDag::default();
+        let (cost, term) = egraph.extract(f, &mut termdag, &sort).unwrap();
+        assert_eq!(
+            termdag.to_string(&term),
+            r#"(unstable-fn "twice" (unstable-fn "twice" (unstable-fn "+" 1)))"#
+        );
+        // One for each function value, plus one for the captured literal
+        assert_eq!(cost, 4);
+    }
+
+    #[test]
+    fn test_term_to_value() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (datatype Math (Num i64) (Add Math Math))
+                (sort MathFn (UnstableFn (Math) Math))
+                (let f (unstable-fn "Add" (Num 1)))
+                "#,
+            )
+            .unwrap();
+        let function = egraph.get_sort::<FunctionSort>().unwrap();
+        let (sort, f) = egraph.eval_expr(&Expr::Var(span!(), "f".into())).unwrap();
+        let mut termdag = TermDag::default();
+        let (_, term) = egraph.extract(f, &mut termdag, &sort).unwrap();
+        assert_eq!(
+            function
+                .term_to_value(&term, &termdag, &mut egraph)
+                .unwrap(),
+            f
+        );
+
+        let num = {
+            let one = termdag.lit(Literal::Int(1));
+            termdag.app("Num".into(), vec![one])
+        };
+        let name = termdag.lit(Literal::String("Add".into()));
+        let one = termdag.lit(Literal::Int(1));
+        let wrong_head = termdag.app("unstable-app".into(), vec![name.clone(), num.clone()]);
+        let no_name = termdag.app("unstable-fn".into(), vec![num.clone()]);
+        let wrong_sort = termdag.app("unstable-fn".into(), vec![name.clone(), one]);
+        let too_many = termdag.app("unstable-fn".into(), vec![name, num.clone(), num]);
+        for term in [wrong_head, no_name, wrong_sort, too_many] {
+            assert!(matches!(
+                function.term_to_value(&term, &termdag, &mut egraph),
+                Err(Error::InvalidFunctionTerm(..))
+            ));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "checked-values")]
+    fn test_checked_values() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        egraph
+            .parse_and_run_program(None, r#"(let f (unstable-fn "+" 1))"#)
+            .unwrap();
+        // A valid index into this sort's table, but a value of another sort
+        let foreign = Value {
+            tag: I64Sort.name(),
+            bits: 0,
+        };
+        let one = 1.store(&I64Sort).unwrap();
+        assert!(matches!(
+            function.apply(&foreign, &[one], &mut egraph),
+            Err(Error::InvalidValue(..))
+        ));
+        assert!(function.inner_values(&foreign).is_empty());
+        let mut termdag = TermDag::default();
+        let extractor = Extractor::new(&egraph, &mut termdag);
+        assert!(matches!(
+            function.try_extract_term(foreign, &extractor, &mut termdag),
+            Err(ExtractionError::InvalidValue(..))
+        ));
+    }
+
+    #[test]
+    fn test_extract_aliased_sorts() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (sort IntFn (UnstableFn (i64) i64))
+                (sort Increment (UnstableFn (i64) i64))
+                (function first () IntFn :no-merge)
+                (function second () Increment :no-merge)
+                (set (first) (unstable-fn "+" 1))
+                (set (second) (unstable-fn "+" 1))
+                "#,
+            )
+            .unwrap();
+        let mut termdag = TermDag::default();
+        let mut extract = |name: &str| {
+            let call = Expr::Call(span!(), name.into(), vec![]);
+            let (sort, value) = egraph.eval_expr(&call).unwrap();
+            let (_, term) = egraph.extract(value, &mut termdag, &sort).unwrap();
+            (sort.name(), term)
+        };
+        let (first_sort, first) = extract("first");
+        let (second_sort, second) = extract("second");
+        assert_eq!(first_sort, second_sort);
+        assert_eq!(first, second);
+        assert_eq!(termdag.to_string(&first), r#"(unstable-fn "+" 1)"#);
+    }
+
+    #[test]
+    fn test_extract_nested_apps() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (datatype Math (Num i64) (Tri i64 String Math))
+                (sort MakeTri (UnstableFn (i64 String Math) Math))
+                (sort CurriedTri (UnstableFn (i64) (UnstableFn (String) (UnstableFn (Math) Math))))
+                (let make-tri (unstable-fn "Tri"))
+                (let with-name (unstable-app (unstable-app (unstable-curry make-tri) 1) "a"))
+                "#,
+            )
+            .unwrap();
+        let mut lookup = |name: &str| {
+            let (sort, value) = egraph.eval_expr(&Expr::Var(span!(), name.into())).unwrap();
+            let function: Arc<FunctionSort> = sort.as_arc_any().downcast().unwrap();
+            (function, value)
+        };
+        let (curried_sort, curried) = lookup("with-name");
+        let (make_tri_sort, make_tri) = lookup("make-tri");
+
+        let mut termdag = TermDag::default();
+        let extract = |extractor: &Extractor, termdag: &mut TermDag| {
+            let (flat_cost, curried) = curried_sort
+                .try_extract_term(curried, extractor, termdag)
+                .unwrap();
+            let (_, make_tri) = make_tri_sort
+                .try_extract_term(make_tri, extractor, termdag)
+                .unwrap();
+            (
+                flat_cost,
+                termdag.to_string(&curried),
+                termdag.to_string(&make_tri),
+            )
+        };
+        let flat = extract(&Extractor::new(&egraph, &mut termdag), &mut termdag);
+        let nested = extract(
+            &Extractor::new_nested_apps(&egraph, &mut termdag),
+            &mut termdag,
+        );
+        assert_eq!(
+            flat.1,
+            r#"(unstable-fn "unstable-curried" (unstable-fn "Tri") 1 "a")"#
+        );
+        assert_eq!(
+            nested.1,
+            r#"(unstable-app (unstable-app (unstable-fn "unstable-curried" (unstable-fn "Tri")) 1) "a")"#
+        );
+        assert_eq!(flat.0, nested.0);
+        // Values that are not curried are flat either way
+        assert_eq!(flat.2, r#"(unstable-fn "Tri")"#);
+        assert_eq!(nested.2, flat.2);
+
+        // Both renderings evaluate back to the same value
+        for rendered in [flat.1, nested.1] {
+            let expr = egraph.parser.get_expr_from_string(None, &rendered).unwrap();
+            let (sort, value) = egraph.eval_expr(&expr).unwrap();
+            assert_eq!(sort.name(), curried_sort.name());
+            assert_eq!(value, curried);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "re-entrant lock of the function values of IntFn")]
+    fn test_reentrant_lock_panics() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        let _functions = function.lock_functions();
+        function.num_interned();
+    }
+
+    #[test]
+    fn test_extract_cyclic_function_value() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        // Interning never produces this, since partial args are stored first
+        let cyclic = {
+            let mut functions = function.lock_functions();
+            let value = Value {
+                #[cfg(any(debug_assertions, feature = "value-tags"))]
+                tag: function.name(),
+                bits: functions.len() as u64,
+            };
+            let sort: ArcSort = function.clone();
+            functions.insert(ValueFunction("twice".into(), vec![(sort, value)]));
+            value
+        };
+        let mut termdag = TermDag::default();
+        let extractor = Extractor::new(&egraph, &mut termdag);
+        assert!(function
+            .extract_term(&egraph, cyclic, &extractor, &mut termdag)
+            .is_none());
+    }
+
+    #[test]
+    fn test_apply_eqsort_output_reuses_eclass() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                "(datatype Math (Num i64) (Neg Math))
+                 (sort MathFn (UnstableFn (Math) Math))
+                 (let neg (unstable-fn \"Neg\"))
+                 (let a (Num 1))
+                 (let b (Num 2))
+                 (let neg-a (Neg a))",
+            )
+            .unwrap();
+        let function = egraph.get_sort::<FunctionSort>().unwrap();
+        let mut eval = |name: &str| {
+            egraph
+                .eval_expr(&Expr::Var(span!(), name.into()))
+                .unwrap()
+                .1
+        };
+        let (neg, a, b, neg_a) = (eval("neg"), eval("a"), eval("b"), eval("neg-a"));
+        let num_negs = |egraph: &EGraph| egraph.functions[&Symbol::from("Neg")].nodes.len();
+        assert_eq!(num_negs(&egraph), 1);
+
+        // Applying to the same arg again finds the existing row
+        assert_eq!(function.apply(&neg, &[a], &mut egraph).unwrap(), neg_a);
+        assert_eq!(num_negs(&egraph), 1);
+
+        // After a union, either arg finds the canonical e-class, even though
+        // one of the values we hold is no longer canonical
+        egraph
+            .parse_and_run_program(None, "(union a b) (run 0)")
+            .unwrap();
+        let math = function.output.clone();
+        let leader = egraph.find(&math, neg_a);
+        assert_eq!(function.apply(&neg, &[a], &mut egraph).unwrap(), leader);
+        assert_eq!(function.apply(&neg, &[b], &mut egraph).unwrap(), leader);
+        assert_eq!(num_negs(&egraph), 1);
+    }
+
+    #[test]
+    fn test_element_sorts() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                "(datatype Math (Num i64))
+                 (sort Show (UnstableFn (Math i64) String))",
+            )
+            .unwrap();
+        let function = egraph.get_sort::<FunctionSort>().unwrap();
+        let names: Vec<Symbol> = function
+            .element_sorts()
+            .iter()
+            .map(|sort| sort.name())
+            .collect();
+        assert_eq!(names, ["Math".into(), "i64".into(), "String".into()]);
+    }
+
+    #[test]
+    fn test_out_of_range_value() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        let mut invalid = Value {
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
+            tag: function.name(),
+            bits: 1000,
+        };
+        let three = 3i64.store(&I64Sort).unwrap();
+
+        assert!(matches!(
+            function.apply(&invalid, &[three], &mut egraph),
+            Err(Error::InvalidValue(_, _))
+        ));
+        assert!(function.inner_values(&invalid).is_empty());
+        assert_eq!(function.serialized_name(&invalid), function.name());
+        assert!(!function.canonicalize(&mut invalid, &egraph.unionfind));
+        assert_eq!(invalid.bits, 1000);
+
+        let name = FunctionName {
+            name: "unstable-fn-name".into(),
+            function: function.clone(),
+        };
+        let output: ArcSort = Arc::new(StringSort);
+        assert!(name.apply(&[invalid], (&[], &output), None).is_none());
+
+        let mut termdag = TermDag::default();
+        let extractor = Extractor::new(&egraph, &mut termdag);
+        assert!(function
+            .extract_term(&egraph, invalid, &extractor, &mut termdag)
+            .is_none());
+    }
+
+    #[test]
+    fn test_intern_stats() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        let before = function.intern_stats();
+        let int: ArcSort = Arc::new(I64Sort);
+        let add_two = || {
+            ValueFunction(
+                "+".into(),
+                vec![(int.clone(), 2i64.store(&I64Sort).unwrap())],
+            )
+        };
+
+        let first = add_two().store(&function).unwrap();
+        let second = add_two().store(&function).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(
+            function.intern_stats(),
+            InternStats {
+                interned: before.interned + 1,
+                dedup_hits: before.dedup_hits + 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_call_without_result() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        let int: ArcSort = Arc::new(I64Sort);
+        let two = 2i64.store(&I64Sort).unwrap();
+        let add_two = ValueFunction("+".into(), vec![(int, two)])
+            .store(&function)
+            .unwrap();
+        // a program for the call that leaves nothing on the stack
+        let program = egraph
+            .compile_actions(&Default::default(), &Default::default())
+            .unwrap();
+        let key = ("+".into(), vec!["i64".into(); 3]);
+        egraph
+            .fn_programs
+            .insert(key, CompiledCall::Program(Arc::new(program)));
+
+        let three = 3i64.store(&I64Sort).unwrap();
+        assert!(matches!(
+            function.apply(&add_two, &[three], &mut egraph),
+            Err(Error::NoCallResult(name)) if name == "+".into()
+        ));
+        let apply = Apply {
+            name: "unstable-app".into(),
+            function: function.clone(),
+        };
+        let sorts: (&[ArcSort], &ArcSort) = (&[], &(function.clone() as ArcSort));
+        assert!(apply
+            .apply(&[add_two, three], sorts, Some(&mut egraph))
+            .is_none());
+    }
+
+    #[test]
+    fn test_apply_checked() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        let int: ArcSort = Arc::new(I64Sort);
+        let string: ArcSort = Arc::new(StringSort);
+        let add_two = ValueFunction(
+            "+".into(),
+            vec![(int.clone(), 2i64.store(&I64Sort).unwrap())],
         )
+        .store(&function)
         .unwrap();
-    let target = mapped_expr.get_corresponding_var_or_lit(&egraph.type_info);
-    let program = egraph.compile_expr(&binding, &actions, &target).unwrap();
-    // Similar to how the `MergeFn::Expr` case is handled in `Egraph::perform_set`
-    // egraph.rebuild().unwrap();
-    let mut stack = vec![];
-    egraph.run_actions(&mut stack, &args, &program).unwrap();
-    stack.pop().unwrap()
+        let three = 3i64.store(&I64Sort).unwrap();
+
+        let five = function
+            .apply_checked(&add_two, &[three], &[int.clone()], &mut egraph)
+            .unwrap();
+        assert_eq!(i64::load(&I64Sort, &five), 5);
+
+        assert!(matches!(
+            function.apply_checked(
+                &add_two,
+                &[three, three],
+                &[int.clone(), int.clone()],
+                &mut egraph
+            ),
+            Err(Error::FunctionValueArity(_, 1, 2))
+        ));
+        // the sorts must match the args too
+        assert!(matches!(
+            function.apply_checked(&add_two, &[three], &[], &mut egraph),
+            Err(Error::FunctionValueArity(_, 0, 1))
+        ));
+        assert!(matches!(
+            function.apply_checked(&add_two, &[three], &[string], &mut egraph),
+            Err(Error::FunctionValueArgSort(_, 0, expected, actual))
+                if expected == "i64".into() && actual == "String".into()
+        ));
+    }
+
+    #[test]
+    fn test_try_extract_term() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (datatype Math (Num i64) (Add Math Math))
+                (constructor Hidden () Math :unextractable)
+                (sort MathFn (UnstableFn (Math) Math))
+                (let shown (unstable-fn "Add" (Num 1)))
+                (let hidden (unstable-fn "Add" (Hidden)))
+                "#,
+            )
+            .unwrap();
+        let function = egraph.get_sort::<FunctionSort>().unwrap();
+        let mut eval = |name: &str| {
+            egraph
+                .eval_expr(&Expr::Var(span!(), name.into()))
+                .unwrap()
+                .1
+        };
+        let (shown, hidden) = (eval("shown"), eval("hidden"));
+
+        let mut termdag = TermDag::default();
+        let extractor = Extractor::new(&egraph, &mut termdag);
+        let (_, term) = function
+            .try_extract_term(shown, &extractor, &mut termdag)
+            .unwrap();
+        assert_eq!(termdag.to_string(&term), r#"(unstable-fn "Add" (Num 1))"#);
+        assert_eq!(
+            function.try_extract_term(hidden, &extractor, &mut termdag),
+            Err(ExtractionError::PartialArg {
+                function: "Add".into(),
+                index: 0,
+                sort: "Math".into(),
+            })
+        );
+        assert!(function
+            .extract_term(&egraph, hidden, &extractor, &mut termdag)
+            .is_none());
+    }
+
+    #[test]
+    fn test_compare_independent_of_interning() {
+        let lets = [
+            r#"(let f1 (unstable-fn "+" 2))"#,
+            r#"(let f2 (unstable-fn "max" 5))"#,
+            r#"(let f3 (unstable-fn "+" 1))"#,
+            r#"(let f4 (unstable-fn "*" 1))"#,
+        ];
+        let sorted = |lets: Vec<&str>| {
+            let mut egraph = EGraph::default();
+            let function = int_fn_sort(&mut egraph);
+            egraph
+                .parse_and_run_program(None, &lets.join("\n"))
+                .unwrap();
+            let mut values: Vec<_> = ["f1", "f2", "f3", "f4"]
+                .into_iter()
+                .map(|name| {
+                    let value = egraph
+                        .eval_expr(&Expr::Var(span!(), name.into()))
+                        .unwrap()
+                        .1;
+                    (name, value)
+                })
+                .collect();
+            values.sort_by(|(_, a), (_, b)| function.compare(a, b));
+            values
+        };
+        let first = sorted(lets.to_vec());
+        let second = sorted(lets.into_iter().rev().collect());
+        // The values were interned in opposite orders
+        assert_ne!(first[0].1, second[0].1);
+        let first: Vec<_> = first.into_iter().map(|(name, _)| name).collect();
+        let second: Vec<_> = second.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(first, ["f4", "f3", "f1", "f2"]);
+        assert_eq!(second, first);
+    }
+
+    #[test]
+    fn test_call_primitive_directly() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (datatype Math (Num i64))
+                (sort IntFn (UnstableFn (i64) i64))
+                (sort ToMath (UnstableFn (i64) Math))
+                (let add-two (unstable-fn "+" 2))
+                (let num (unstable-fn "Num"))
+                (let five (unstable-app add-two 3))
+                (let three (unstable-app num 3))
+                "#,
+            )
+            .unwrap();
+        let key = ("+".into(), vec!["i64".into(); 3]);
+        assert!(matches!(
+            egraph.fn_programs.get(&key),
+            Some(CompiledCall::Primitive(_))
+        ));
+        // Constructors still run as actions
+        let key = ("Num".into(), vec!["i64".into(), "Math".into()]);
+        assert!(matches!(
+            egraph.fn_programs.get(&key),
+            Some(CompiledCall::Program(_))
+        ));
+    }
+
+    #[test]
+    fn test_call_error() {
+        let mut egraph = EGraph::default();
+        let function = int_fn_sort(&mut egraph);
+        let int: ArcSort = Arc::new(I64Sort);
+        let ten = 10i64.store(&I64Sort).unwrap();
+        let divide_ten = ValueFunction("/".into(), vec![(int, ten)])
+            .store(&function)
+            .unwrap();
+
+        let zero = 0i64.store(&I64Sort).unwrap();
+        let err = function
+            .apply(&divide_ten, &[zero], &mut egraph)
+            .unwrap_err();
+        match err {
+            Error::CallError(name, args, err) => {
+                assert_eq!(name, "/".into());
+                assert_eq!(args, vec![ten, zero]);
+                assert!(matches!(*err, Error::PrimitiveError(_, _)));
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+        let two = 2i64.store(&I64Sort).unwrap();
+        assert_eq!(
+            function.apply(&divide_ten, &[two], &mut egraph).unwrap(),
+            5i64.store(&I64Sort).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_batch() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (datatype Math (Num i64) (Add Math Math))
+                (sort MathFn (UnstableFn (Math) Math))
+                (let a (Num 1))
+                (let b (Num 2))
+                (let fa (unstable-fn "Add" a))
+                (let fb (unstable-fn "Add" b))
+                (union a b)
+                "#,
+            )
+            .unwrap();
+        let function = egraph.get_sort::<FunctionSort>().unwrap();
+        let mut eval = |name: &str| {
+            let expr = Expr::Var(span!(), name.into());
+            egraph.eval_expr(&expr).unwrap().1
+        };
+        let (fa, fb) = (eval("fa"), eval("fb"));
+
+        let mut serial = [fa, fb, fa];
+        let mut serial_changed = false;
+        for value in serial.iter_mut() {
+            serial_changed |= function.canonicalize(value, &egraph.unionfind);
+        }
+        let mut batch = [fa, fb, fa];
+        let batch_changed = function.canonicalize_batch(&mut batch, &egraph.unionfind);
+        assert_eq!(batch, serial);
+        assert_eq!(batch_changed, serial_changed);
+        assert_eq!(batch[0], batch[1]);
+        assert!(!function.canonicalize_batch(&mut batch, &egraph.unionfind));
+    }
+
+    #[test]
+    fn test_canonicalize_primitive_args() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (datatype Math (Num i64) (Scale i64 Math))
+                (sort MathFn (UnstableFn (Math) Math))
+                (let f (unstable-fn "Scale" 2))
+                "#,
+            )
+            .unwrap();
+        let function = egraph.get_sort::<FunctionSort>().unwrap();
+        let f = egraph.eval_expr(&Expr::Var(span!(), "f".into())).unwrap().1;
+        let before = function.intern_stats();
+        let mut values = [f, f];
+        assert!(!function.canonicalize(&mut values[0], &egraph.unionfind));
+        assert!(!function.canonicalize_batch(&mut values, &egraph.unionfind));
+        assert_eq!(values, [f, f]);
+        // Nothing was stored again
+        assert_eq!(function.intern_stats(), before);
+    }
+
+    #[test]
+    fn test_rename_function() {
+        let mut egraph = EGraph::default();
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (sort IntFn (UnstableFn (i64) i64))
+                (function foo (i64 i64) i64 :no-merge)
+                (set (foo 1 2) 3)
+                (let f (unstable-fn "foo" 1))
+                (function foo_v2 (i64 i64) i64 :no-merge)
+                (set (foo_v2 1 2) 4)
+                "#,
+            )
+            .unwrap();
+        let function = egraph.get_sort::<FunctionSort>().unwrap();
+        let f = egraph.eval_expr(&Expr::Var(span!(), "f".into())).unwrap().1;
+        function.rename_function("foo".into(), "foo_v2".into());
+
+        // The value stored before the rename applies the new function
+        egraph
+            .parse_and_run_program(
+                None,
+                r#"
+                (check (= (unstable-fn-name f) "foo_v2"))
+                (let applied (unstable-app f 2))
+                (check (= applied 4))
+                (let g (unstable-fn "foo_v2" 1))
+                "#,
+            )
+            .unwrap();
+        let g = egraph.eval_expr(&Expr::Var(span!(), "g".into())).unwrap().1;
+        assert_ne!(f, g);
+        let mut canonical = f;
+        assert!(function.canonicalize(&mut canonical, &egraph.unionfind));
+        assert_eq!(canonical, g);
+        let mut values = [f, g];
+        assert!(function.canonicalize_batch(&mut values, &egraph.unionfind));
+        assert_eq!(values, [g, g]);
+
+        // Renaming back makes it load as the old function again
+        function.rename_function("foo_v2".into(), "foo".into());
+        egraph
+            .parse_and_run_program(None, "(let back (unstable-app f 2)) (check (= back 3))")
+            .unwrap();
+        assert!(function.canonicalize(&mut canonical, &egraph.unionfind));
+        assert_eq!(canonical, f);
+    }
 }
@@ -4,6 +4,7 @@ use super::*;
 
 /// Signed 64-bit integers supporting these primitives:
 /// - Arithmetic: `+`, `-`, `*`, `/`, `%`
+/// - Modular arithmetic: `mod`, `pow-mod`, `gcd`
 /// - Bitwise: `&`, `|`, `^`, `<<`, `>>`, `not-i64`
 /// - Fallible comparisons: `<`, `>`, `<=`, `>=`
 /// - Boolean comparisons: `bool-=`, `bool-<`, `bool->`, `bool-<=`, `bool->=`
@@ -16,6 +17,11 @@ use super::*;
 /// ```
 /// On the other hand, boolean comparisons will always match, and so
 /// make sense to use inside expressions.
+///
+/// Unlike `%`, `(mod a b)` is the Euclidean remainder and is never negative.
+/// `(pow-mod base exp m)` computes `base^exp` modulo `m` in the same range,
+/// and `(gcd a b)` is always non-negative. All of them fail on a zero modulus,
+/// a negative exponent, or a result that does not fit in an `i64`.
 #[derive(Debug)]
 pub struct I64Sort;
 
@@ -32,6 +38,10 @@ impl Sort for I64Sort {
         self
     }
 
+    fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        i64::load(self, a).cmp(&i64::load(self, b))
+    }
+
     #[rustfmt::skip]
     fn register_primitives(self: Arc<Self>, typeinfo: &mut TypeInfo) {
         typeinfo.add_primitive(TermOrderingMin {
@@ -47,6 +57,10 @@ impl Sort for I64Sort {
         add_primitives!(typeinfo, "/" = |a: i64, b: i64| -> Opt<i64> { a.checked_div(b) });
         add_primitives!(typeinfo, "%" = |a: i64, b: i64| -> Opt<i64> { a.checked_rem(b) });
 
+        add_primitives!(typeinfo, "mod" = |a: i64, b: i64| -> Opt<i64> { a.checked_rem_euclid(b) });
+        add_primitives!(typeinfo, "pow-mod" = |base: i64, exp: i64, m: i64| -> Opt<i64> { pow_mod(base, exp, m) });
+        add_primitives!(typeinfo, "gcd" = |a: i64, b: i64| -> Opt<i64> { gcd(a, b) });
+
         add_primitives!(typeinfo, "&" = |a: i64, b: i64| -> i64 { a & b });
         add_primitives!(typeinfo, "|" = |a: i64, b: i64| -> i64 { a | b });
         add_primitives!(typeinfo, "^" = |a: i64, b: i64| -> i64 { a ^ b });
@@ -96,7 +110,7 @@ impl IntoSort for i64 {
     type Sort = I64Sort;
     fn store(self, _sort: &Self::Sort) -> Option<Value> {
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: I64Sort.name(),
             bits: self as u64,
         })
@@ -110,6 +124,34 @@ impl FromSort for i64 {
     }
 }
 
+/// Computes `base^exp mod m` by repeated squaring, in `O(log exp)` steps.
+fn pow_mod(base: i64, exp: i64, m: i64) -> Option<i64> {
+    if m == 0 || exp < 0 {
+        return None;
+    }
+    let m = (m as i128).abs();
+    let mut base = (base as i128).rem_euclid(m) as u128;
+    let m = m as u128;
+    let mut exp = exp as u64;
+    let mut result = 1 % m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % m;
+        }
+        base = base * base % m;
+        exp >>= 1;
+    }
+    i64::try_from(result).ok()
+}
+
+fn gcd(a: i64, b: i64) -> Option<i64> {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    i64::try_from(a).ok()
+}
+
 struct CountMatches {
     name: Symbol,
     string: Arc<StringSort>,
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Mutex;
 
 use crate::constraint::{AllEqualTypeConstraint, SimpleTypeConstraint};
@@ -15,6 +15,40 @@ type ValueMap = BTreeMap<Value, Value>;
 /// - `map-not-contains`
 /// - `map-remove`
 /// - `map-length`
+/// - `map-merge-collect` (only for maps whose values are a `Vec`, see below)
+/// - `map-keys`, `map-values` (only if a `Vec` of the keys or values is declared)
+/// - `map-retain-keys`, `map-remove-keys` (only if a `Vec` of the keys is declared)
+/// - `map-sorted-entries` (only if a `Vec` of `(Pair K V)` is declared)
+/// - `map-fold` (only if a function sort `(Acc K V) Acc` is declared)
+/// - `map-zip-with` (only if a function sort `(V V) V` is declared)
+/// - `map-bimap` (see below)
+///
+/// The primitives that need another sort are registered whichever of the sorts
+/// is declared last.
+///
+/// Entries are ordered by the key's underlying value, which is also the order
+/// of the vecs returned by `map-keys` and `map-values` and the order in which
+/// `(map-fold f init m)` calls `(f acc k v)`. For non-negative `i64` keys this
+/// is numeric order, but in general (e.g. for negative integers, strings, or
+/// eqsorts) it is only a deterministic order, unrelated to the meaning of the keys.
+///
+/// `(map-sorted-entries m)` returns the entries of `m` as a vec of pairs, ordered by
+/// the key sort's comparison instead (e.g. numeric order for all `i64` keys).
+///
+/// `(map-zip-with f default-a default-b a b)` returns a map over the keys of
+/// both `a` and `b`, where each key maps to `(f va vb)`, using `default-a` or
+/// `default-b` in place of a value missing from `a` or `b`.
+///
+/// When the map sorts `(Map K V)` and `(Map K (Vec V))` are both declared,
+/// `(map-merge-collect a b)` takes two `(Map K V)`s and returns a
+/// `(Map K (Vec V))` mapping every key to the values it has in `a` followed
+/// by the values it has in `b`.
+///
+/// When the map sorts `(Map K V)` and `(Map K2 V2)` and the function sorts
+/// `(K) K2` and `(V) V2` are all declared, `(map-bimap keyfn valfn m)` returns the
+/// `(Map K2 V2)` mapping `(keyfn k)` to `(valfn v)` for every entry of `m`. If
+/// `keyfn` maps several keys to the same new key, the entry that comes last in
+/// `m` wins, as if the entries were inserted in order with `map-insert`.
 #[derive(Debug)]
 pub struct MapSort {
     name: Symbol,
@@ -31,6 +65,152 @@ impl MapSort {
     fn value(&self) -> ArcSort {
         self.value.clone()
     }
+
+    /// Registers the primitives that combine this map sort with the vec sort `vec`:
+    /// `map-keys`, `map-retain-keys` and `map-remove-keys` if `vec` holds keys,
+    /// `map-values` if it holds values, and `map-sorted-entries` if it holds pairs
+    /// of a key and a value.
+    /// Called when the later of the two sorts is declared.
+    pub(crate) fn register_vec_primitives(
+        self: Arc<Self>,
+        vec: Arc<VecSort>,
+        typeinfo: &mut TypeInfo,
+    ) {
+        let element_name = vec.element_name();
+        if element_name == self.key.name() {
+            typeinfo.add_primitive(Keys {
+                name: "map-keys".into(),
+                map: self.clone(),
+                vec: vec.clone(),
+            });
+            typeinfo.add_primitive(FilterKeys {
+                name: "map-retain-keys".into(),
+                map: self.clone(),
+                vec: vec.clone(),
+                retain: true,
+            });
+            typeinfo.add_primitive(FilterKeys {
+                name: "map-remove-keys".into(),
+                map: self.clone(),
+                vec: vec.clone(),
+                retain: false,
+            });
+        }
+        if element_name == self.value.name() {
+            typeinfo.add_primitive(Values {
+                name: "map-values".into(),
+                map: self.clone(),
+                vec: vec.clone(),
+            });
+        }
+        if let Ok(pair) = vec.element().as_arc_any().downcast::<PairSort>() {
+            if pair.first().name() == self.key.name() && pair.second().name() == self.value.name() {
+                typeinfo.add_primitive(SortedEntries {
+                    name: "map-sorted-entries".into(),
+                    map: self,
+                    pair,
+                    vec,
+                });
+            }
+        }
+    }
+
+    /// Registers the primitives that combine this map sort with the function sort `fn_`:
+    /// `map-fold` and `map-zip-with` (see [`MapSort::register_folds`]), and `map-bimap`
+    /// into this map from any map `fn_` converts the keys or values of.
+    /// Called when `fn_` is declared after this map.
+    pub(crate) fn register_fn_primitives(
+        self: Arc<Self>,
+        fn_: Arc<FunctionSort>,
+        typeinfo: &mut TypeInfo,
+    ) {
+        self.register_folds(fn_.clone(), typeinfo);
+        if fn_.inputs.len() != 1 {
+            return;
+        }
+        let (from, to) = (fn_.inputs[0].name(), fn_.output.name());
+        for input in declared_sorts::<MapSort>(typeinfo) {
+            let converts_keys = input.key.name() == from && self.key.name() == to;
+            let converts_values = input.value.name() == from && self.value.name() == to;
+            if converts_keys || converts_values {
+                Self::register_bimap(input, self.clone(), typeinfo);
+            }
+        }
+    }
+
+    /// Registers `map-fold` if `fn_` is `(Acc K V) Acc`, and `map-zip-with` if it is
+    /// `(V V) V`.
+    fn register_folds(self: &Arc<Self>, fn_: Arc<FunctionSort>, typeinfo: &mut TypeInfo) {
+        let (key_name, value_name) = (self.key.name(), self.value.name());
+        let inputs: Vec<Symbol> = fn_.inputs.iter().map(|input| input.name()).collect();
+        if inputs.len() == 3
+            && inputs[0] == fn_.output.name()
+            && inputs[1] == key_name
+            && inputs[2] == value_name
+        {
+            typeinfo.add_primitive(Fold {
+                name: "map-fold".into(),
+                map: self.clone(),
+                fn_: fn_.clone(),
+            });
+        }
+        if inputs == [value_name, value_name] && fn_.output.name() == value_name {
+            typeinfo.add_primitive(ZipWith {
+                name: "map-zip-with".into(),
+                map: self.clone(),
+                fn_,
+            });
+        }
+    }
+
+    /// Registers `map-bimap` from `input` to `output` if function sorts converting
+    /// the keys and the values of `input` to those of `output` have been declared.
+    fn register_bimap(input: Arc<Self>, output: Arc<Self>, typeinfo: &mut TypeInfo) {
+        let unary_fn = |from: Symbol, to: Symbol| {
+            typeinfo.get_sort_by(|s: &Arc<FunctionSort>| {
+                s.inputs.len() == 1 && s.inputs[0].name() == from && s.output.name() == to
+            })
+        };
+        if let (Some(key_fn), Some(value_fn)) = (
+            unary_fn(input.key.name(), output.key.name()),
+            unary_fn(input.value.name(), output.value.name()),
+        ) {
+            typeinfo.add_primitive(Bimap {
+                name: "map-bimap".into(),
+                input,
+                key_fn,
+                value_fn,
+                output,
+            });
+        }
+    }
+
+    /// Registers `map-merge-collect` from `input` to `output` if `output` maps the
+    /// keys of `input` to vecs of its values.
+    fn register_merge_collect(input: Arc<Self>, output: Arc<Self>, typeinfo: &mut TypeInfo) {
+        let Ok(vec) = output.value().as_arc_any().downcast::<VecSort>() else {
+            return;
+        };
+        if input.key.name() == output.key.name() && input.value.name() == vec.element_name() {
+            typeinfo.add_primitive(MergeCollect {
+                name: "map-merge-collect".into(),
+                input,
+                vec,
+                output,
+            });
+        }
+    }
+}
+
+/// The sorts of type `T` declared so far, skipping aliases, which would register
+/// the same primitives again.
+fn declared_sorts<T: Sort>(typeinfo: &TypeInfo) -> Vec<Arc<T>> {
+    typeinfo
+        .sorts
+        .iter()
+        .filter(|(name, sort)| **name == sort.name())
+        .filter_map(|(_, sort)| sort.clone().as_arc_any().downcast().ok())
+        .collect()
 }
 
 impl Presort for MapSort {
@@ -48,6 +228,15 @@ impl Presort for MapSort {
             "map-contains".into(),
             "map-remove".into(),
             "map-length".into(),
+            "map-merge-collect".into(),
+            "map-keys".into(),
+            "map-values".into(),
+            "map-fold".into(),
+            "map-zip-with".into(),
+            "map-bimap".into(),
+            "map-retain-keys".into(),
+            "map-remove-keys".into(),
+            "map-sorted-entries".into(),
         ]
     }
 
@@ -75,7 +264,7 @@ impl Presort for MapSort {
                 ));
             }
 
-            if v.is_container_sort() {
+            if v.is_eq_container_sort() {
                 return Err(TypeError::DisallowedSort(
                     name,
                     "Maps nested with other EqSort containers are not allowed".into(),
@@ -112,6 +301,18 @@ impl Sort for MapSort {
         self.key.is_eq_sort() || self.value.is_eq_sort()
     }
 
+    fn element_sorts(&self) -> Vec<ArcSort> {
+        vec![self.key.clone(), self.value.clone()]
+    }
+
+    fn num_interned(&self) -> usize {
+        self.maps.lock().unwrap().len()
+    }
+
+    fn truncate_to(&self, len: usize) {
+        self.maps.lock().unwrap().truncate(len);
+    }
+
     fn inner_values(&self, value: &Value) -> Vec<(ArcSort, Value)> {
         let maps = self.maps.lock().unwrap();
         let map = maps.get_index(value.bits as usize).unwrap();
@@ -172,8 +373,26 @@ impl Sort for MapSort {
         });
         typeinfo.add_primitive(Length {
             name: "map-length".into(),
-            map: self,
+            map: self.clone(),
         });
+        // Primitives that also need a vec or function sort are registered by
+        // whichever of the sorts is declared last
+        for vec in declared_sorts::<VecSort>(typeinfo) {
+            self.clone().register_vec_primitives(vec, typeinfo);
+        }
+        for fn_ in declared_sorts::<FunctionSort>(typeinfo) {
+            self.register_folds(fn_, typeinfo);
+        }
+        // Any map declared so far can be mapped into this one, or this one into it.
+        // The list includes this map itself.
+        for other in declared_sorts::<MapSort>(typeinfo) {
+            Self::register_bimap(other.clone(), self.clone(), typeinfo);
+            Self::register_merge_collect(other.clone(), self.clone(), typeinfo);
+            if other.name != self.name {
+                Self::register_bimap(self.clone(), other.clone(), typeinfo);
+                Self::register_merge_collect(self.clone(), other, typeinfo);
+            }
+        }
     }
 
     fn extract_term(
@@ -202,7 +421,7 @@ impl IntoSort for ValueMap {
         let mut maps = sort.maps.lock().unwrap();
         let (i, _) = maps.insert_full(self);
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: sort.name,
             bits: i as u64,
         })
@@ -538,3 +757,338 @@ impl PrimitiveLike for Length {
         Some(Value::from(map.len() as i64))
     }
 }
+
+struct Keys {
+    name: Symbol,
+    map: Arc<MapSort>,
+    vec: Arc<VecSort>,
+}
+
+impl PrimitiveLike for Keys {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.map.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let map = ValueMap::load(&self.map, &values[0]);
+        let keys: Vec<Value> = map.into_keys().collect();
+        keys.store(&self.vec)
+    }
+}
+
+// (map-retain-keys m keys) keeps the entries of m whose key is in keys, and
+// (map-remove-keys m keys) drops them. Keys missing from m are ignored.
+struct FilterKeys {
+    name: Symbol,
+    map: Arc<MapSort>,
+    vec: Arc<VecSort>,
+    retain: bool,
+}
+
+impl PrimitiveLike for FilterKeys {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.map.clone(), self.vec.clone(), self.map.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        // Compare canonical keys when we can, as either side may be stale
+        let find = |k: Value| match &egraph {
+            Some(egraph) => egraph.find(&self.map.key, k),
+            None => k,
+        };
+        let map = ValueMap::load(&self.map, &values[0]);
+        let keys: BTreeSet<Value> = Vec::<Value>::load(&self.vec, &values[1])
+            .into_iter()
+            .map(find)
+            .collect();
+        let filtered: ValueMap = map
+            .into_iter()
+            .filter(|(k, _)| keys.contains(&find(*k)) == self.retain)
+            .collect();
+        filtered.store(&self.map)
+    }
+}
+
+struct Values {
+    name: Symbol,
+    map: Arc<MapSort>,
+    vec: Arc<VecSort>,
+}
+
+impl PrimitiveLike for Values {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.map.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let map = ValueMap::load(&self.map, &values[0]);
+        let values: Vec<Value> = map.into_values().collect();
+        values.store(&self.vec)
+    }
+}
+
+// (map-sorted-entries m) returns the entries of m as pairs, ordered by `Sort::compare` on the keys
+struct SortedEntries {
+    name: Symbol,
+    map: Arc<MapSort>,
+    pair: Arc<PairSort>,
+    vec: Arc<VecSort>,
+}
+
+impl PrimitiveLike for SortedEntries {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.map.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let map = ValueMap::load(&self.map, &values[0]);
+        let mut entries: Vec<(Value, Value)> = map.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| self.map.key.compare(a, b));
+        let pairs = entries
+            .into_iter()
+            .map(|entry| entry.store(&self.pair))
+            .collect::<Option<Vec<Value>>>()?;
+        pairs.store(&self.vec)
+    }
+}
+
+// (map-fold f init map) calls (f acc k v) on every entry in key order
+struct Fold {
+    name: Symbol,
+    map: Arc<MapSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Fold {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.fn_.clone(),
+                self.fn_.output.clone(),
+                self.map.clone(),
+                self.fn_.output.clone(),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let map = ValueMap::load(&self.map, &values[2]);
+        let mut acc = values[1];
+        for (k, v) in map {
+            acc = self.fn_.apply(&values[0], &[acc, k, v], egraph).ok()?;
+            // The function may have created or merged e-classes, so keep the
+            // accumulator canonical before passing it to the next step
+            self.fn_.output.canonicalize(&mut acc, &egraph.unionfind);
+        }
+        Some(acc)
+    }
+}
+
+// (map-zip-with f default-a default-b a b)
+struct ZipWith {
+    name: Symbol,
+    map: Arc<MapSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for ZipWith {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.fn_.clone(),
+                self.map.value(),
+                self.map.value(),
+                self.map.clone(),
+                self.map.clone(),
+                self.map.clone(),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let (default_a, default_b) = (values[1], values[2]);
+        let a = ValueMap::load(&self.map, &values[3]);
+        let b = ValueMap::load(&self.map, &values[4]);
+        let keys: BTreeSet<Value> = a.keys().chain(b.keys()).copied().collect();
+        let zipped: ValueMap = keys
+            .into_iter()
+            .map(|k| {
+                let va = a.get(&k).copied().unwrap_or(default_a);
+                let vb = b.get(&k).copied().unwrap_or(default_b);
+                Some((k, self.fn_.apply(&values[0], &[va, vb], egraph).ok()?))
+            })
+            .collect::<Option<_>>()?;
+        zipped.store(&self.map)
+    }
+}
+
+struct Bimap {
+    name: Symbol,
+    input: Arc<MapSort>,
+    key_fn: Arc<FunctionSort>,
+    value_fn: Arc<FunctionSort>,
+    output: Arc<MapSort>,
+}
+
+impl PrimitiveLike for Bimap {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.key_fn.clone(),
+                self.value_fn.clone(),
+                self.input.clone(),
+                self.output.clone(),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let map = ValueMap::load(&self.input, &values[2]);
+        let mut mapped = ValueMap::new();
+        for (k, v) in map {
+            let k = self.key_fn.apply(&values[0], &[k], egraph).ok()?;
+            let v = self.value_fn.apply(&values[1], &[v], egraph).ok()?;
+            mapped.insert(k, v);
+        }
+        mapped.store(&self.output)
+    }
+}
+
+struct MergeCollect {
+    name: Symbol,
+    input: Arc<MapSort>,
+    vec: Arc<VecSort>,
+    output: Arc<MapSort>,
+}
+
+impl PrimitiveLike for MergeCollect {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.input.clone(), self.input.clone(), self.output.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let mut collected: BTreeMap<Value, Vec<Value>> = BTreeMap::new();
+        for map in values {
+            for (k, v) in ValueMap::load(&self.input, map) {
+                collected.entry(k).or_default().push(v);
+            }
+        }
+        let map: ValueMap = collected
+            .into_iter()
+            .map(|(k, vs)| Some((k, vs.store(&self.vec)?)))
+            .collect::<Option<_>>()?;
+        map.store(&self.output)
+    }
+}
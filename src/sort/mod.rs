@@ -1,6 +1,7 @@
 #[macro_use]
 mod macros;
 use lazy_static::lazy_static;
+use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::{any::Any, sync::Arc};
 
@@ -8,10 +9,14 @@ mod bigint;
 pub use bigint::*;
 mod bigrat;
 pub use bigrat::*;
+mod bitvec;
+pub use bitvec::*;
 mod bool;
 pub use self::bool::*;
 mod string;
 pub use string::*;
+mod stringbuilder;
+pub use stringbuilder::*;
 mod unit;
 pub use unit::*;
 mod i64;
@@ -28,6 +33,8 @@ mod r#fn;
 pub use r#fn::*;
 mod multiset;
 pub use multiset::*;
+mod pair;
+pub use pair::*;
 
 use crate::constraint::AllEqualTypeConstraint;
 use crate::extract::{Cost, Extractor};
@@ -53,6 +60,37 @@ pub trait Sort: Any + Send + Sync + Debug {
         false
     }
 
+    /// The sorts of the values a container of this sort may hold, known from the
+    /// declaration alone. Empty for sorts that are not containers.
+    fn element_sorts(&self) -> Vec<ArcSort> {
+        vec![]
+    }
+
+    /// The number of values interned in this sort's table so far.
+    ///
+    /// Only sorts that intern their values (such as containers) override this.
+    /// Their values store the index into the table as their bits, and entries
+    /// are only removed by [`Sort::truncate_to`], so a count is enough to tell which
+    /// values are new.
+    fn num_interned(&self) -> usize {
+        0
+    }
+
+    /// The size of this sort's interning table, to later restore with [`Sort::truncate_to`].
+    fn snapshot_len(&self) -> usize {
+        self.num_interned()
+    }
+
+    /// Remove the values interned after [`Sort::snapshot_len`] returned `len`, which is
+    /// how `pop` undoes the values interned since the matching `push`.
+    ///
+    /// Values interned before keep their indices. Sorts that intern their values in a
+    /// table owned by the sort override this. The default does nothing, which suits
+    /// sorts without a table, and ones whose table is shared by every e-graph.
+    fn truncate_to(&self, len: usize) {
+        let _ = len;
+    }
+
     // Only eq_container_sort need to implement this method,
     // which returns a list of ids to be tracked.
     fn foreach_tracked_values<'a>(
@@ -79,6 +117,14 @@ pub trait Sort: Any + Send + Sync + Debug {
         false
     }
 
+    /// Compare two values of this sort, e.g. for `vec-cummax`.
+    ///
+    /// Defaults to comparing the underlying bits, which is deterministic but only
+    /// meaningful for sorts whose values are ordered like their bits.
+    fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        a.cmp(b)
+    }
+
     /// Return the serialized name of the sort
     ///
     /// Only used for container sorts, which cannot be serialized with make_expr so need an explicit name
@@ -97,6 +143,77 @@ pub trait Sort: Any + Send + Sync + Debug {
         let _ = info;
     }
 
+    /// The name of the primitive that succeeds when two values of this sort are equal,
+    /// and fails otherwise, if the sort has one. `name` is the name the sort is declared
+    /// under, which differs from [`Sort::name`] for aliases.
+    ///
+    /// Container sorts get `<name>-eq` by default, which compares the interned indices of
+    /// the canonicalized values, so containers are equal when their contents are.
+    /// Sorts may override this, for example to pick a name shared by all their aliases.
+    fn eq_primitive_name(&self, name: Symbol) -> Option<Symbol> {
+        self.is_container_sort()
+            .then(|| format!("{name}-eq").into())
+    }
+
+    /// Canonicalize each of `values`, like [`Sort::canonicalize`], and return whether any
+    /// changed. Sorts may override this to share work between the values.
+    fn canonicalize_batch(&self, values: &mut [Value], unionfind: &UnionFind) -> bool {
+        let mut changed = false;
+        for value in values {
+            changed |= self.canonicalize(value, unionfind);
+        }
+        changed
+    }
+
+    /// Render a value for debugging, without running extraction over the e-graph.
+    ///
+    /// Eq sort values are shown by their canonical e-class, as `Math-3`, and container
+    /// values as their serialized name followed by their inner values, as `f[1, "hi"]`.
+    fn format_value(&self, value: &Value, egraph: &EGraph) -> String {
+        if self.is_eq_sort() {
+            let bits = egraph.unionfind.find(value.bits);
+            format!("{}-{}", self.name(), bits)
+        } else if self.is_container_sort() {
+            let inner: Vec<String> = self
+                .inner_values(value)
+                .iter()
+                .map(|(sort, value)| sort.format_value(value, egraph))
+                .collect();
+            format!("{}[{}]", self.serialized_name(value), inner.join(", "))
+        } else {
+            let mut termdag = TermDag::default();
+            let extractor = Extractor::without_costs(egraph);
+            match self.extract_term(egraph, *value, &extractor, &mut termdag) {
+                Some((_, term)) => termdag.to_string(&term),
+                None => format!("{}-{}", self.name(), value.bits),
+            }
+        }
+    }
+
+    /// A string that identifies a value up to equality in the e-graph, for use as a key.
+    ///
+    /// Equal values get equal keys. Container values are canonicalized first, so containers
+    /// whose inner values were unioned get the same key, and their inner values are keyed
+    /// recursively.
+    fn canonical_key(&self, value: &Value, egraph: &EGraph) -> String {
+        if !self.is_container_sort() {
+            return self.format_value(value, egraph);
+        }
+        let mut value = *value;
+        self.canonicalize(&mut value, &egraph.unionfind);
+        let inner: Vec<String> = self
+            .inner_values(&value)
+            .iter()
+            .map(|(sort, value)| sort.canonical_key(value, egraph))
+            .collect();
+        format!(
+            "{}:{}[{}]",
+            self.name(),
+            self.serialized_name(&value),
+            inner.join(", ")
+        )
+    }
+
     /// Extracting a term (with smallest cost) out of a primitive value
     fn extract_term(
         &self,
@@ -121,6 +238,54 @@ pub trait Presort {
     ) -> Result<ArcSort, TypeError>;
 }
 
+/// What a declared sort is, see [`EGraph::sort_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortInfo {
+    /// The name of the sort, which differs from the name it was looked up by for an alias.
+    pub name: Symbol,
+    pub is_eq_sort: bool,
+    pub is_container_sort: bool,
+    pub is_eq_container_sort: bool,
+    /// The names of [`Sort::element_sorts`], empty unless this is a container sort.
+    pub element_sorts: Vec<Symbol>,
+    pub kind: SortKind,
+}
+
+/// The signature of the sorts that have one, see [`SortInfo`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SortKind {
+    /// A [`FunctionSort`], by the names of its inputs and output.
+    Function {
+        inputs: Vec<Symbol>,
+        output: Symbol,
+        /// Whether the last input may be repeated.
+        variadic: bool,
+    },
+    /// Any other sort.
+    Other,
+}
+
+impl SortInfo {
+    pub fn new(sort: &ArcSort) -> Self {
+        let kind = match sort.clone().as_arc_any().downcast::<FunctionSort>() {
+            Ok(function) => SortKind::Function {
+                inputs: function.inputs.iter().map(|s| s.name()).collect(),
+                output: function.output.name(),
+                variadic: function.variadic,
+            },
+            Err(_) => SortKind::Other,
+        };
+        SortInfo {
+            name: sort.name(),
+            is_eq_sort: sort.is_eq_sort(),
+            is_container_sort: sort.is_container_sort(),
+            is_eq_container_sort: sort.is_eq_container_sort(),
+            element_sorts: sort.element_sorts().iter().map(|s| s.name()).collect(),
+            kind,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct EqSort {
     pub name: Symbol,
@@ -213,6 +378,70 @@ impl PrimitiveLike for ValueEq {
     }
 }
 
+/// Structural equality of two container values, see [`Sort::eq_primitive_name`].
+pub(crate) struct ContainerEq {
+    pub(crate) name: Symbol,
+    pub(crate) sort: ArcSort,
+}
+
+impl PrimitiveLike for ContainerEq {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.sort.clone(), self.sort.clone(), Arc::new(UnitSort)],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let (mut a, mut b) = (values[0], values[1]);
+        // Values from the database are already canonical, but ones computed since the
+        // last rebuild may not be
+        if let Some(egraph) = egraph {
+            self.sort.canonicalize(&mut a, &egraph.unionfind);
+            self.sort.canonicalize(&mut b, &egraph.unionfind);
+        }
+        (a == b).then(Value::unit)
+    }
+}
+
+pub(crate) struct CanonicalKey;
+
+impl PrimitiveLike for CanonicalKey {
+    fn name(&self) -> Symbol {
+        "canonical-key".into()
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        AllEqualTypeConstraint::new(self.name(), span.clone())
+            .with_exact_length(2)
+            .with_output_sort(Arc::new(StringSort))
+            .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name()));
+        let key = sorts.0[0].canonical_key(&values[0], egraph);
+        Symbol::from(key).store(&StringSort)
+    }
+}
+
 pub fn literal_sort(lit: &Literal) -> ArcSort {
     match lit {
         Literal::Int(_) => Arc::new(I64Sort) as ArcSort,
@@ -57,6 +57,15 @@ mod inner {
             new
         }
 
+        /// Like `map`, but returns `None` if `f` fails on any element.
+        pub(crate) fn try_map(self, mut f: impl FnMut(&T) -> Option<T>) -> Option<MultiSet<T>> {
+            let mut new = MultiSet::new();
+            for (k, v) in self.0.into_iter() {
+                new.insert_multiple_mut(f(&k)?, v);
+            }
+            Some(new)
+        }
+
         /// Insert a value into the multiset, taking ownership of it and returning a new multiset.
         pub(crate) fn insert(mut self, value: T) -> MultiSet<T> {
             self.insert_multiple_mut(value, 1);
@@ -190,6 +199,18 @@ impl Sort for MultiSetSort {
         self.element.is_eq_sort()
     }
 
+    fn element_sorts(&self) -> Vec<ArcSort> {
+        vec![self.element.clone()]
+    }
+
+    fn num_interned(&self) -> usize {
+        self.multisets.lock().unwrap().len()
+    }
+
+    fn truncate_to(&self, len: usize) {
+        self.multisets.lock().unwrap().truncate(len);
+    }
+
     fn inner_values(&self, value: &Value) -> Vec<(ArcSort, Value)> {
         let multisets = self.multisets.lock().unwrap();
         let multiset = multisets.get_index(value.bits as usize).unwrap();
@@ -291,7 +312,7 @@ impl IntoSort for ValueMultiSet {
         let mut multisets = sort.multisets.lock().unwrap();
         let (i, _) = multisets.insert_full(self);
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: sort.name,
             bits: i as u64,
         })
@@ -608,7 +629,7 @@ impl PrimitiveLike for Map {
         let egraph =
             egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
         let multiset = ValueMultiSet::load(&self.multiset, &values[1]);
-        let new_multiset = multiset.map(|e| self.fn_.apply(&values[0], &[*e], egraph));
+        let new_multiset = multiset.try_map(|e| self.fn_.apply(&values[0], &[*e], egraph).ok())?;
         new_multiset.store(&self.multiset)
     }
 }
@@ -0,0 +1,248 @@
+use std::sync::Mutex;
+
+use crate::constraint::SimpleTypeConstraint;
+
+use super::*;
+
+type ValuePair = (Value, Value);
+
+/// A pair of two values, possibly of different sorts, supporting these primitives:
+/// - `pair`
+/// - `pair-first`
+/// - `pair-second`
+/// - `vec-span` or `set-partition`, when both sorts are the same vec or set sort
+///   (see `VecSort::register_span` and `SetSort::register_partition`)
+#[derive(Debug)]
+pub struct PairSort {
+    name: Symbol,
+    first: ArcSort,
+    second: ArcSort,
+    pairs: Mutex<IndexSet<ValuePair>>,
+}
+
+impl PairSort {
+    pub fn first(&self) -> ArcSort {
+        self.first.clone()
+    }
+
+    pub fn second(&self) -> ArcSort {
+        self.second.clone()
+    }
+}
+
+impl Presort for PairSort {
+    fn presort_name() -> Symbol {
+        "Pair".into()
+    }
+
+    fn reserved_primitives() -> Vec<Symbol> {
+        vec!["pair".into(), "pair-first".into(), "pair-second".into()]
+    }
+
+    fn make_sort(
+        typeinfo: &mut TypeInfo,
+        name: Symbol,
+        args: &[Expr],
+    ) -> Result<ArcSort, TypeError> {
+        if let [Expr::Var(a_span, a), Expr::Var(b_span, b)] = args {
+            let a = typeinfo
+                .sorts
+                .get(a)
+                .ok_or(TypeError::UndefinedSort(*a, a_span.clone()))?;
+            let b = typeinfo
+                .sorts
+                .get(b)
+                .ok_or(TypeError::UndefinedSort(*b, b_span.clone()))?;
+
+            for (sort, span) in [(a, a_span), (b, b_span)] {
+                if sort.is_eq_container_sort() {
+                    return Err(TypeError::DisallowedSort(
+                        name,
+                        "Pairs nested with other EqSort containers are not allowed".into(),
+                        span.clone(),
+                    ));
+                }
+            }
+
+            Ok(Arc::new(Self {
+                name,
+                first: a.clone(),
+                second: b.clone(),
+                pairs: Default::default(),
+            }))
+        } else {
+            panic!("Pair sort must have two sorts as arguments. Got {:?}", args)
+        }
+    }
+}
+
+impl Sort for PairSort {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn as_arc_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static> {
+        self
+    }
+
+    fn is_container_sort(&self) -> bool {
+        true
+    }
+
+    fn is_eq_container_sort(&self) -> bool {
+        self.first.is_eq_sort() || self.second.is_eq_sort()
+    }
+
+    fn element_sorts(&self) -> Vec<ArcSort> {
+        vec![self.first.clone(), self.second.clone()]
+    }
+
+    fn num_interned(&self) -> usize {
+        self.pairs.lock().unwrap().len()
+    }
+
+    fn truncate_to(&self, len: usize) {
+        self.pairs.lock().unwrap().truncate(len);
+    }
+
+    fn inner_values(&self, value: &Value) -> Vec<(ArcSort, Value)> {
+        let (a, b) = ValuePair::load(self, value);
+        vec![(self.first.clone(), a), (self.second.clone(), b)]
+    }
+
+    fn canonicalize(&self, value: &mut Value, unionfind: &UnionFind) -> bool {
+        let (mut a, mut b) = ValuePair::load(self, value);
+        let changed = self.first.canonicalize(&mut a, unionfind)
+            | self.second.canonicalize(&mut b, unionfind);
+        *value = (a, b).store(self).unwrap();
+        changed
+    }
+
+    fn register_primitives(self: Arc<Self>, typeinfo: &mut TypeInfo) {
+        // Primitives splitting a container in two return a pair of that container
+        if self.first.name() == self.second.name() {
+            if let Ok(vec) = self.first.clone().as_arc_any().downcast::<VecSort>() {
+                vec.register_span(self.clone(), typeinfo);
+            }
+            if let Ok(set) = self.first.clone().as_arc_any().downcast::<SetSort>() {
+                set.register_partition(self.clone(), typeinfo);
+            }
+        }
+        typeinfo.add_primitive(Ctor {
+            name: "pair".into(),
+            pair: self.clone(),
+        });
+        typeinfo.add_primitive(Project {
+            name: "pair-first".into(),
+            pair: self.clone(),
+            second: false,
+        });
+        typeinfo.add_primitive(Project {
+            name: "pair-second".into(),
+            pair: self,
+            second: true,
+        });
+    }
+
+    fn extract_term(
+        &self,
+        _egraph: &EGraph,
+        value: Value,
+        extractor: &Extractor,
+        termdag: &mut TermDag,
+    ) -> Option<(Cost, Term)> {
+        let (a, b) = ValuePair::load(self, &value);
+        let (a_cost, a) = extractor.find_best(a, termdag, &self.first)?;
+        let (b_cost, b) = extractor.find_best(b, termdag, &self.second)?;
+        Some((
+            a_cost.saturating_add(b_cost),
+            termdag.app("pair".into(), vec![a, b]),
+        ))
+    }
+
+    fn serialized_name(&self, _value: &Value) -> Symbol {
+        "pair".into()
+    }
+}
+
+impl IntoSort for ValuePair {
+    type Sort = PairSort;
+    fn store(self, sort: &Self::Sort) -> Option<Value> {
+        let mut pairs = sort.pairs.lock().unwrap();
+        let (i, _) = pairs.insert_full(self);
+        Some(Value {
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
+            tag: sort.name,
+            bits: i as u64,
+        })
+    }
+}
+
+impl FromSort for ValuePair {
+    type Sort = PairSort;
+    fn load(sort: &Self::Sort, value: &Value) -> Self {
+        let pairs = sort.pairs.lock().unwrap();
+        *pairs.get_index(value.bits as usize).unwrap()
+    }
+}
+
+struct Ctor {
+    name: Symbol,
+    pair: Arc<PairSort>,
+}
+
+impl PrimitiveLike for Ctor {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.pair.first(), self.pair.second(), self.pair.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        (values[0], values[1]).store(&self.pair)
+    }
+}
+
+struct Project {
+    name: Symbol,
+    pair: Arc<PairSort>,
+    second: bool,
+}
+
+impl PrimitiveLike for Project {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        let output = if self.second {
+            self.pair.second()
+        } else {
+            self.pair.first()
+        };
+        SimpleTypeConstraint::new(self.name(), vec![self.pair.clone(), output], span.clone())
+            .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let (a, b) = ValuePair::load(&self.pair, &values[0]);
+        Some(if self.second { b } else { a })
+    }
+}
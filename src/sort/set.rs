@@ -22,6 +22,30 @@ impl SetSort {
     pub fn element_name(&self) -> Symbol {
         self.element.name()
     }
+
+    /// Registers `set-partition`, which returns its two halves in `halves`, a pair of this
+    /// set sort. Called when that pair sort is declared, if a predicate over this set's
+    /// elements was declared before it.
+    pub(crate) fn register_partition(
+        self: Arc<Self>,
+        halves: Arc<PairSort>,
+        typeinfo: &mut TypeInfo,
+    ) {
+        let inner_name = self.element.name();
+        let fn_sort = typeinfo.get_sort_by(|s: &Arc<FunctionSort>| {
+            s.output.name() == BoolSort.name()
+                && s.inputs.len() == 1
+                && s.inputs[0].name() == inner_name
+        });
+        if let Some(fn_sort) = fn_sort {
+            typeinfo.add_primitive(Partition {
+                name: "set-partition".into(),
+                set: self,
+                halves,
+                fn_: fn_sort,
+            });
+        }
+    }
 }
 
 impl Presort for SetSort {
@@ -42,6 +66,9 @@ impl Presort for SetSort {
             "set-intersect".into(),
             "set-get".into(),
             "set-length".into(),
+            "set-map".into(),
+            "set-partition".into(),
+            "set-combinations".into(),
         ]
     }
 
@@ -92,6 +119,18 @@ impl Sort for SetSort {
         self.element.is_eq_sort()
     }
 
+    fn element_sorts(&self) -> Vec<ArcSort> {
+        vec![self.element.clone()]
+    }
+
+    fn num_interned(&self) -> usize {
+        self.sets.lock().unwrap().len()
+    }
+
+    fn truncate_to(&self, len: usize) {
+        self.sets.lock().unwrap().truncate(len);
+    }
+
     fn inner_values(&self, value: &Value) -> Vec<(ArcSort, Value)> {
         // TODO: Potential duplication of code
         let sets = self.sets.lock().unwrap();
@@ -167,8 +206,36 @@ impl Sort for SetSort {
         });
         typeinfo.add_primitive(Intersect {
             name: "set-intersect".into(),
-            set: self,
+            set: self.clone(),
         });
+
+        // Only include combinations if this is a set of sets
+        if let Ok(inner) = self.element.clone().as_arc_any().downcast::<SetSort>() {
+            typeinfo.add_primitive(Combinations {
+                name: "set-combinations".into(),
+                set: inner,
+                subsets: self.clone(),
+            });
+        }
+
+        // Only include map if we already declared a function sort into this set's
+        // elements, and a set of that function's inputs (which may be this set)
+        let inner_name = self.element.name();
+        let fn_sort = typeinfo.get_sort_by(|s: &Arc<FunctionSort>| {
+            s.output.name() == inner_name && s.inputs.len() == 1
+        });
+        if let Some(fn_sort) = fn_sort {
+            let input_name = fn_sort.inputs[0].name();
+            let input = typeinfo.get_sort_by(|s: &Arc<SetSort>| s.element_name() == input_name);
+            if let Some(input) = input {
+                typeinfo.add_primitive(Map {
+                    name: "set-map".into(),
+                    input,
+                    output: self,
+                    fn_: fn_sort,
+                });
+            }
+        }
     }
 
     fn extract_term(
@@ -200,7 +267,7 @@ impl IntoSort for ValueSet {
         let mut sets = sort.sets.lock().unwrap();
         let (i, _) = sets.insert_full(self);
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: sort.name,
             bits: i as u64,
         })
@@ -592,3 +659,152 @@ impl PrimitiveLike for Diff {
         set1.store(&self.set)
     }
 }
+
+// (set-map <function> <set>)
+struct Map {
+    name: Symbol,
+    input: Arc<SetSort>,
+    output: Arc<SetSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Map {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.input.clone(), self.output.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let set = ValueSet::load(&self.input, &values[1]);
+        let new_set: ValueSet = set
+            .iter()
+            .map(|e| self.fn_.apply(&values[0], &[*e], egraph).ok())
+            .collect::<Option<_>>()?;
+        new_set.store(&self.output)
+    }
+}
+
+// (set-partition pred set) returns (pair matching non-matching)
+struct Partition {
+    name: Symbol,
+    set: Arc<SetSort>,
+    halves: Arc<PairSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Partition {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.set.clone(), self.halves.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let set = ValueSet::load(&self.set, &values[1]);
+        let (mut matching, mut rest) = (ValueSet::new(), ValueSet::new());
+        for e in set {
+            let result = self.fn_.apply(&values[0], &[e], egraph).ok()?;
+            if bool::load(&BoolSort, &result) {
+                matching.insert(e);
+            } else {
+                rest.insert(e);
+            }
+        }
+        (matching.store(&self.set)?, rest.store(&self.set)?).store(&self.halves)
+    }
+}
+
+/// The most subsets `set-combinations` returns before failing instead.
+const MAX_COMBINATIONS: usize = 10_000;
+
+// (set-combinations k set) returns all subsets of set with k elements
+struct Combinations {
+    name: Symbol,
+    set: Arc<SetSort>,
+    subsets: Arc<SetSort>,
+}
+
+impl Combinations {
+    /// The binomial coefficient `n choose k`, or `None` if it overflows.
+    fn count(n: usize, k: usize) -> Option<usize> {
+        let k = k.min(n - k);
+        (0..k).try_fold(1usize, |count, i| Some(count.checked_mul(n - i)? / (i + 1)))
+    }
+}
+
+impl PrimitiveLike for Combinations {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![Arc::new(I64Sort), self.set.clone(), self.subsets.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let k = usize::try_from(i64::load(&I64Sort, &values[0])).ok()?;
+        let elements: Vec<Value> = ValueSet::load(&self.set, &values[1]).into_iter().collect();
+        let n = elements.len();
+        if k > n {
+            return ValueSet::new().store(&self.subsets);
+        }
+        if Self::count(n, k)? > MAX_COMBINATIONS {
+            return None;
+        }
+
+        // Step through the k-element index lists in lexicographic order
+        let mut indices: Vec<usize> = (0..k).collect();
+        let mut subsets = ValueSet::new();
+        loop {
+            let subset: ValueSet = indices.iter().map(|&i| elements[i]).collect();
+            subsets.insert(subset.store(&self.set)?);
+            let Some(i) = (0..k).rev().find(|&i| indices[i] != i + n - k) else {
+                break;
+            };
+            indices[i] += 1;
+            for j in i + 1..k {
+                indices[j] = indices[j - 1] + 1;
+            }
+        }
+        subsets.store(&self.subsets)
+    }
+}
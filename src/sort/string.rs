@@ -41,6 +41,10 @@ impl Sort for StringSort {
         });
         typeinfo.add_primitive(Replace {
             name: "replace".into(),
+            string: self.clone(),
+        });
+        typeinfo.add_primitive(Levenshtein {
+            name: "levenshtein".into(),
             string: self,
         });
     }
@@ -52,7 +56,7 @@ impl IntoSort for Symbol {
     type Sort = StringSort;
     fn store(self, _sort: &Self::Sort) -> Option<Value> {
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: StringSort.name(),
             bits: NonZeroU32::from(self).get() as _,
         })
@@ -128,3 +132,58 @@ impl PrimitiveLike for Replace {
         Some(Value::from(res))
     }
 }
+
+/// Edit distance between two strings, counting insertions, deletions, and
+/// substitutions of Unicode scalar values.
+struct Levenshtein {
+    name: Symbol,
+    string: Arc<StringSort>,
+}
+
+impl PrimitiveLike for Levenshtein {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.string.clone(), self.string.clone(), Arc::new(I64Sort)],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let a: Vec<char> = Symbol::load(&self.string, &values[0])
+            .as_str()
+            .chars()
+            .collect();
+        let b: Vec<char> = Symbol::load(&self.string, &values[1])
+            .as_str()
+            .chars()
+            .collect();
+        Some(Value::from(levenshtein(&a, &b) as i64))
+    }
+}
+
+/// Keeps only the previous row of the DP table, so it uses `O(b.len())` memory.
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        // `diag` holds the entry up and to the left of the one being computed
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diag + usize::from(ca != cb);
+            diag = row[j + 1];
+            row[j + 1] = substitute.min(diag + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
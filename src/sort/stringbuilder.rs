@@ -0,0 +1,219 @@
+//! A string built up from pieces, for rules that build strings incrementally.
+//!
+//! `(sb-new)` is the empty builder, `(sb-push sb s)` appends the string `s`, and
+//! `(sb-build sb)` returns the whole string. Repeatedly appending with `+` interns every
+//! intermediate string, which takes quadratic time overall. A builder instead only records
+//! the builder it extends and the piece pushed onto it, so pushing takes constant time and
+//! building takes time linear in the length of the result.
+//!
+//! Builders are equal when the same pieces were pushed in the same order, so two builders
+//! can differ even if they build the same string.
+use std::sync::Mutex;
+
+use crate::{ast::Literal, util::IndexSet};
+
+use super::*;
+
+lazy_static! {
+    static ref STRING_BUILDER_SORT_NAME: Symbol = "StringBuilder".into();
+}
+
+/// A builder as the index of the builder it extends and the piece pushed onto it,
+/// or `None` for the empty builder.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct StringBuilder(Option<(usize, Symbol)>);
+
+impl StringBuilder {
+    /// The pieces pushed onto the empty builder to make this one, in order.
+    pub fn pieces(&self, sort: &StringBuilderSort) -> Vec<Symbol> {
+        let builders = sort.builders.lock().unwrap();
+        let mut pieces = vec![];
+        let mut builder = self;
+        while let StringBuilder(Some((prefix, piece))) = builder {
+            pieces.push(*piece);
+            builder = builders.get_index(*prefix).unwrap();
+        }
+        pieces.reverse();
+        pieces
+    }
+
+    /// The concatenation of all pieces.
+    pub fn build(&self, sort: &StringBuilderSort) -> String {
+        self.pieces(sort)
+            .iter()
+            .map(|piece| piece.as_str())
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StringBuilderSort {
+    builders: Mutex<IndexSet<StringBuilder>>,
+}
+
+impl Sort for StringBuilderSort {
+    fn name(&self) -> Symbol {
+        *STRING_BUILDER_SORT_NAME
+    }
+
+    fn as_arc_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static> {
+        self
+    }
+
+    fn num_interned(&self) -> usize {
+        self.builders.lock().unwrap().len()
+    }
+
+    fn truncate_to(&self, len: usize) {
+        self.builders.lock().unwrap().truncate(len);
+    }
+
+    fn register_primitives(self: Arc<Self>, eg: &mut TypeInfo) {
+        eg.add_primitive(New {
+            name: "sb-new".into(),
+            builder: self.clone(),
+        });
+        eg.add_primitive(Push {
+            name: "sb-push".into(),
+            builder: self.clone(),
+            string: eg.get_sort_nofail(),
+        });
+        eg.add_primitive(Build {
+            name: "sb-build".into(),
+            builder: self,
+            string: eg.get_sort_nofail(),
+        });
+    }
+
+    fn extract_term(
+        &self,
+        _egraph: &EGraph,
+        value: Value,
+        _extractor: &Extractor,
+        termdag: &mut TermDag,
+    ) -> Option<(Cost, Term)> {
+        #[cfg(debug_assertions)]
+        debug_assert_eq!(value.tag, self.name());
+
+        let pieces = StringBuilder::load(self, &value).pieces(self);
+        let mut term = termdag.app("sb-new".into(), vec![]);
+        for piece in &pieces {
+            let piece = termdag.lit(Literal::String(*piece));
+            term = termdag.app("sb-push".into(), vec![term, piece]);
+        }
+        Some((1 + pieces.len(), term))
+    }
+}
+
+impl FromSort for StringBuilder {
+    type Sort = StringBuilderSort;
+    fn load(sort: &Self::Sort, value: &Value) -> Self {
+        let i = value.bits as usize;
+        sort.builders.lock().unwrap().get_index(i).unwrap().clone()
+    }
+}
+
+impl IntoSort for StringBuilder {
+    type Sort = StringBuilderSort;
+    fn store(self, sort: &Self::Sort) -> Option<Value> {
+        let (i, _) = sort.builders.lock().unwrap().insert_full(self);
+        Some(Value {
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
+            tag: sort.name(),
+            bits: i as u64,
+        })
+    }
+}
+
+struct New {
+    name: Symbol,
+    builder: Arc<StringBuilderSort>,
+}
+
+impl PrimitiveLike for New {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(self.name(), vec![self.builder.clone()], span.clone()).into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        assert!(values.is_empty());
+        StringBuilder::default().store(&self.builder)
+    }
+}
+
+struct Push {
+    name: Symbol,
+    builder: Arc<StringBuilderSort>,
+    string: Arc<StringSort>,
+}
+
+impl PrimitiveLike for Push {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.builder.clone(),
+                self.string.clone(),
+                self.builder.clone(),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        // The builder being extended is already interned at `values[0].bits`
+        let piece = Symbol::load(&self.string, &values[1]);
+        StringBuilder(Some((values[0].bits as usize, piece))).store(&self.builder)
+    }
+}
+
+struct Build {
+    name: Symbol,
+    builder: Arc<StringBuilderSort>,
+    string: Arc<StringSort>,
+}
+
+impl PrimitiveLike for Build {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.builder.clone(), self.string.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let builder = StringBuilder::load(&self.builder, &values[0]);
+        let string: Symbol = builder.build(&self.builder).into();
+        string.store(&self.string)
+    }
+}
@@ -1,5 +1,7 @@
 use std::sync::Mutex;
 
+use num::{rational::BigRational, Zero};
+
 use crate::constraint::AllEqualTypeConstraint;
 
 use super::*;
@@ -21,6 +23,142 @@ impl VecSort {
     pub fn element_name(&self) -> Symbol {
         self.element.name()
     }
+
+    /// Registers `vec-span`, which returns its two halves in `halves`, a pair of this vec
+    /// sort. Called when that pair sort is declared, if a predicate over this vec's
+    /// elements was declared before it.
+    pub(crate) fn register_span(self: Arc<Self>, halves: Arc<PairSort>, typeinfo: &mut TypeInfo) {
+        let element_name = self.element_name();
+        let fn_sort = typeinfo.get_sort_by(|s: &Arc<FunctionSort>| {
+            s.output.name() == BoolSort.name()
+                && s.inputs.len() == 1
+                && s.inputs[0].name() == element_name
+        });
+        if let Some(fn_sort) = fn_sort {
+            typeinfo.add_primitive(VecSpan {
+                name: "vec-span".into(),
+                vec: self,
+                halves,
+                fn_: fn_sort,
+            });
+        }
+    }
+
+    /// Registers the primitives that combine this vec sort with the function sort `fn_`:
+    /// `vec-flat-map-indexed` if `fn_` takes an index and an element and returns a vec,
+    /// `fn-agree-on` and `unstable-app-all` if `fn_` takes a single element,
+    /// `apply-trace`, `string-join-map` or `rational-sum-map` if it maps an element to an
+    /// element, a string or a rational, `vec-reduce` if it combines two elements,
+    /// `vec-chunk-reduce` if `fn_` folds elements into an accumulator,
+    /// `fn-capture-sorts` if this is a vec of strings, and `unstable-can-apply?` and
+    /// `unstable-fn-args` always.
+    /// Called when the later of the two sorts is declared, since the function may return
+    /// this vec sort itself.
+    pub(crate) fn register_fn_primitives(
+        self: Arc<Self>,
+        fn_: Arc<FunctionSort>,
+        typeinfo: &mut TypeInfo,
+    ) {
+        typeinfo.add_primitive(CanApply {
+            name: "unstable-can-apply?".into(),
+            vec: self.clone(),
+            fn_: fn_.clone(),
+        });
+        typeinfo.add_primitive(FnArgs {
+            name: "unstable-fn-args".into(),
+            vec: self.clone(),
+            fn_: fn_.clone(),
+        });
+        if self.element_name() == StringSort.name() {
+            typeinfo.add_primitive(CaptureSorts {
+                name: "fn-capture-sorts".into(),
+                vec: self.clone(),
+                fn_: fn_.clone(),
+            });
+        }
+        if fn_.inputs.len() == 1 && fn_.inputs[0].name() == self.element_name() {
+            let output_name = fn_.output.name();
+            if output_name == self.element_name() {
+                typeinfo.add_primitive(ApplyTrace {
+                    name: "apply-trace".into(),
+                    vec: self.clone(),
+                    fn_: fn_.clone(),
+                });
+            } else if output_name == StringSort.name() {
+                typeinfo.add_primitive(StringJoinMap {
+                    name: "string-join-map".into(),
+                    vec: self.clone(),
+                    fn_: fn_.clone(),
+                });
+            } else if output_name == BigRatSort.name() {
+                typeinfo.add_primitive(RationalSumMap {
+                    name: "rational-sum-map".into(),
+                    vec: self.clone(),
+                    fn_: fn_.clone(),
+                });
+            }
+            typeinfo.add_primitive(FnAgreeOn {
+                name: "fn-agree-on".into(),
+                vec: self.clone(),
+                fn_: fn_.clone(),
+            });
+            // A vec of the results declared after both sorts registers this itself
+            if let Some(output) =
+                typeinfo.get_sort_by(|s: &Arc<VecSort>| s.element_name() == output_name)
+            {
+                typeinfo.add_primitive(AppAll {
+                    name: "unstable-app-all".into(),
+                    fn_: fn_.clone(),
+                    input: self.clone(),
+                    output,
+                });
+            }
+        }
+        if fn_.inputs.len() == 2
+            && fn_
+                .inputs
+                .iter()
+                .all(|input| input.name() == self.element_name())
+            && fn_.output.name() == self.element_name()
+        {
+            typeinfo.add_primitive(Reduce {
+                name: "vec-reduce".into(),
+                vec: self.clone(),
+                fn_: fn_.clone(),
+            });
+        }
+        if fn_.inputs.len() == 2
+            && fn_.inputs[1].name() == self.element_name()
+            && fn_.inputs[0].name() == fn_.output.name()
+        {
+            // A vec of the results declared after both sorts registers this itself
+            let acc_name = fn_.output.name();
+            if let Some(output) =
+                typeinfo.get_sort_by(|s: &Arc<VecSort>| s.element_name() == acc_name)
+            {
+                typeinfo.add_primitive(ChunkReduce {
+                    name: "vec-chunk-reduce".into(),
+                    fn_: fn_.clone(),
+                    vec: self.clone(),
+                    output,
+                });
+            }
+        }
+        if fn_.inputs.len() != 2
+            || fn_.inputs[0].name() != I64Sort.name()
+            || fn_.inputs[1].name() != self.element_name()
+        {
+            return;
+        }
+        if let Ok(output) = fn_.output.clone().as_arc_any().downcast::<VecSort>() {
+            typeinfo.add_primitive(FlatMapIndexed {
+                name: "vec-flat-map-indexed".into(),
+                vec: self,
+                output,
+                fn_,
+            });
+        }
+    }
 }
 
 impl Presort for VecSort {
@@ -41,6 +179,28 @@ impl Presort for VecSort {
             "vec-get".into(),
             "vec-set".into(),
             "vec-remove".into(),
+            "vec-shuffle".into(),
+            "vec-distinct".into(),
+            "vec-interleave".into(),
+            "vec-cummax".into(),
+            "vec-cummin".into(),
+            "i64-sum".into(),
+            "i64-min".into(),
+            "i64-max".into(),
+            "vec-transpose".into(),
+            "vec-span".into(),
+            "vec-reduce".into(),
+            "apply-trace".into(),
+            "string-join-map".into(),
+            "rational-sum-map".into(),
+            "vec-flat-map-indexed".into(),
+            "fn-agree-on".into(),
+            "vec-sort-by-precomputed".into(),
+            "unstable-can-apply?".into(),
+            "unstable-app-all".into(),
+            "fn-capture-sorts".into(),
+            "unstable-fn-args".into(),
+            "vec-chunk-reduce".into(),
         ]
     }
 
@@ -91,6 +251,18 @@ impl Sort for VecSort {
         self.element.is_eq_sort()
     }
 
+    fn element_sorts(&self) -> Vec<ArcSort> {
+        vec![self.element.clone()]
+    }
+
+    fn num_interned(&self) -> usize {
+        self.vecs.lock().unwrap().len()
+    }
+
+    fn truncate_to(&self, len: usize) {
+        self.vecs.lock().unwrap().truncate(len);
+    }
+
     fn inner_values(&self, value: &Value) -> Vec<(ArcSort, Value)> {
         // TODO: Potential duplication of code
         let vecs = self.vecs.lock().unwrap();
@@ -166,8 +338,166 @@ impl Sort for VecSort {
         });
         typeinfo.add_primitive(Remove {
             name: "vec-remove".into(),
-            vec: self,
-        })
+            vec: self.clone(),
+        });
+        typeinfo.add_primitive(Shuffle {
+            name: "vec-shuffle".into(),
+            vec: self.clone(),
+        });
+        typeinfo.add_primitive(Distinct {
+            name: "vec-distinct".into(),
+            vec: self.clone(),
+        });
+        typeinfo.add_primitive(Interleave {
+            name: "vec-interleave".into(),
+            vec: self.clone(),
+        });
+        typeinfo.add_primitive(Cumulative {
+            name: "vec-cummax".into(),
+            vec: self.clone(),
+            keep: Ordering::Greater,
+        });
+        typeinfo.add_primitive(Cumulative {
+            name: "vec-cummin".into(),
+            vec: self.clone(),
+            keep: Ordering::Less,
+        });
+        // Any vec can be the keys of any other, including this sort itself
+        let vec_sorts: Vec<Arc<VecSort>> = typeinfo
+            .sorts
+            .values()
+            .filter_map(|sort| sort.clone().as_arc_any().downcast().ok())
+            .collect();
+        for other in vec_sorts {
+            if other.name != self.name {
+                typeinfo.add_primitive(SortByPrecomputed {
+                    name: "vec-sort-by-precomputed".into(),
+                    keys: self.clone(),
+                    vec: other.clone(),
+                });
+            }
+            typeinfo.add_primitive(SortByPrecomputed {
+                name: "vec-sort-by-precomputed".into(),
+                keys: other,
+                vec: self.clone(),
+            });
+        }
+
+        if self.element.name() == I64Sort.name() {
+            for (name, reduce) in [
+                (
+                    "i64-sum",
+                    (|v| v.iter().try_fold(0i64, |acc, x| acc.checked_add(*x)))
+                        as fn(&[i64]) -> Option<i64>,
+                ),
+                ("i64-min", |v| v.iter().copied().min()),
+                ("i64-max", |v| v.iter().copied().max()),
+            ] {
+                typeinfo.add_primitive(I64Reduce {
+                    name: name.into(),
+                    vec: self.clone(),
+                    reduce,
+                });
+            }
+        }
+
+        if let Ok(row) = self.element.clone().as_arc_any().downcast::<VecSort>() {
+            typeinfo.add_primitive(Transpose {
+                name: "vec-transpose".into(),
+                vec: self.clone(),
+                row,
+            });
+        }
+
+        // `unstable-fn-table` needs a vec of rows and a vec of function values, so
+        // whichever is declared last registers it with each earlier one
+        let vec_sorts: Vec<Arc<VecSort>> = typeinfo
+            .sorts
+            .iter()
+            .filter(|(name, sort)| **name == sort.name())
+            .filter_map(|(_, sort)| sort.clone().as_arc_any().downcast().ok())
+            .collect();
+        if let Ok(function) = self.element.clone().as_arc_any().downcast::<FunctionSort>() {
+            for rows in &vec_sorts {
+                function
+                    .clone()
+                    .register_table(rows.clone(), self.clone(), typeinfo);
+            }
+        } else if self
+            .element
+            .clone()
+            .as_arc_any()
+            .downcast::<VecSort>()
+            .is_ok()
+        {
+            for result in &vec_sorts {
+                if let Ok(function) = result
+                    .element
+                    .clone()
+                    .as_arc_any()
+                    .downcast::<FunctionSort>()
+                {
+                    function.register_table(self.clone(), result.clone(), typeinfo);
+                }
+            }
+        }
+
+        let map_sorts: Vec<Arc<MapSort>> = typeinfo
+            .sorts
+            .iter()
+            .filter(|(name, sort)| **name == sort.name())
+            .filter_map(|(_, sort)| sort.clone().as_arc_any().downcast().ok())
+            .collect();
+        for map_sort in map_sorts {
+            map_sort.register_vec_primitives(self.clone(), typeinfo);
+        }
+
+        let inner_name = self.element.name();
+        // Skip aliases, which would register the same primitive again
+        let fn_sorts: Vec<Arc<FunctionSort>> = typeinfo
+            .sorts
+            .iter()
+            .filter(|(name, sort)| **name == sort.name())
+            .filter_map(|(_, sort)| sort.clone().as_arc_any().downcast().ok())
+            .collect();
+        for fn_sort in fn_sorts {
+            // This is the vec of results for `unstable-app-all` over an earlier vec of args
+            if fn_sort.inputs.len() == 1
+                && fn_sort.output.name() == inner_name
+                && fn_sort.inputs[0].name() != inner_name
+            {
+                let input_name = fn_sort.inputs[0].name();
+                if let Some(input) =
+                    typeinfo.get_sort_by(|s: &Arc<VecSort>| s.element_name() == input_name)
+                {
+                    typeinfo.add_primitive(AppAll {
+                        name: "unstable-app-all".into(),
+                        fn_: fn_sort.clone(),
+                        input,
+                        output: self.clone(),
+                    });
+                }
+            }
+            // This is the vec of results for `vec-chunk-reduce` over an earlier vec
+            if fn_sort.inputs.len() == 2
+                && fn_sort.output.name() == inner_name
+                && fn_sort.inputs[0].name() == inner_name
+                && fn_sort.inputs[1].name() != inner_name
+            {
+                let element_name = fn_sort.inputs[1].name();
+                if let Some(vec) =
+                    typeinfo.get_sort_by(|s: &Arc<VecSort>| s.element_name() == element_name)
+                {
+                    typeinfo.add_primitive(ChunkReduce {
+                        name: "vec-chunk-reduce".into(),
+                        fn_: fn_sort.clone(),
+                        vec,
+                        output: self.clone(),
+                    });
+                }
+            }
+            self.clone().register_fn_primitives(fn_sort, typeinfo);
+        }
     }
 
     fn extract_term(
@@ -207,7 +537,7 @@ impl IntoSort for ValueVec {
         let mut vecs = sort.vecs.lock().unwrap();
         let (i, _) = vecs.insert_full(self);
         Some(Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: sort.name,
             bits: i as u64,
         })
@@ -597,38 +927,854 @@ impl PrimitiveLike for Remove {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+struct Shuffle {
+    name: Symbol,
+    vec: Arc<VecSort>,
+}
 
-    #[test]
-    fn test_vec_make_expr() {
-        let mut egraph = EGraph::default();
-        let outputs = egraph
-            .parse_and_run_program(
-                None,
-                r#"
-            (sort IVec (Vec i64))
-            (let v0 (vec-empty))
-            (let v1 (vec-of 1 2 3 4))
-            (extract v0)
-            (extract v1)
-            "#,
-            )
-            .unwrap();
+impl PrimitiveLike for Shuffle {
+    fn name(&self) -> Symbol {
+        self.name
+    }
 
-        // Check extracted expr is parsed as an original expr
-        egraph
-            .parse_and_run_program(
-                None,
-                &format!(
-                    r#"
-                (check (= v0 {}))
-                (check (= v1 {}))
-                "#,
-                    outputs[0], outputs[1],
-                ),
-            )
-            .unwrap();
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![Arc::new(I64Sort), self.vec.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let seed = i64::load(&I64Sort, &values[0]);
+        let mut vec = ValueVec::load(&self.vec, &values[1]);
+        shuffle(&mut vec, seed as u64);
+        vec.store(&self.vec)
+    }
+}
+
+// (vec-distinct v) keeps only the first occurrence of each element, comparing
+// canonical elements when an e-graph is available.
+struct Distinct {
+    name: Symbol,
+    vec: Arc<VecSort>,
+}
+
+impl PrimitiveLike for Distinct {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.vec.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let find = |e: Value| match &egraph {
+            Some(egraph) => egraph.find(&self.vec.element, e),
+            None => e,
+        };
+        let mut seen = HashSet::default();
+        let mut vec = ValueVec::load(&self.vec, &values[0]);
+        vec.retain(|e| seen.insert(find(*e)));
+        vec.store(&self.vec)
+    }
+}
+
+// (vec-interleave a b) alternates the elements of a and b, starting with a,
+// followed by the rest of the longer one
+struct Interleave {
+    name: Symbol,
+    vec: Arc<VecSort>,
+}
+
+impl PrimitiveLike for Interleave {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        AllEqualTypeConstraint::new(self.name(), span.clone())
+            .with_all_arguments_sort(self.vec.clone())
+            .with_exact_length(3)
+            .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let a = ValueVec::load(&self.vec, &values[0]);
+        let b = ValueVec::load(&self.vec, &values[1]);
+        let common = a.len().min(b.len());
+        let mut vec: ValueVec = a.iter().zip(&b).flat_map(|(x, y)| [*x, *y]).collect();
+        vec.extend_from_slice(&a[common..]);
+        vec.extend_from_slice(&b[common..]);
+        vec.store(&self.vec)
+    }
+}
+
+// Reductions over a vec of i64s that don't need a function value.
+// `i64-sum` fails on overflow like `+`, and `i64-min`/`i64-max` fail on an empty vec.
+struct I64Reduce {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    reduce: fn(&[i64]) -> Option<i64>,
+}
+
+impl PrimitiveLike for I64Reduce {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.vec.clone(), Arc::new(I64Sort)],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let vec = ValueVec::load(&self.vec, &values[0]);
+        let ints: Vec<i64> = vec.iter().map(|v| i64::load(&I64Sort, v)).collect();
+        (self.reduce)(&ints)?.store(&I64Sort)
+    }
+}
+
+// (vec-sort-by-precomputed keys v) stably sorts v by the parallel vec of keys, using
+// `Sort::compare` on the keys, and fails unless both have the same length.
+struct SortByPrecomputed {
+    name: Symbol,
+    keys: Arc<VecSort>,
+    vec: Arc<VecSort>,
+}
+
+impl PrimitiveLike for SortByPrecomputed {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.keys.clone(), self.vec.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let keys = ValueVec::load(&self.keys, &values[0]);
+        let vec = ValueVec::load(&self.vec, &values[1]);
+        if keys.len() != vec.len() {
+            return None;
+        }
+        let mut pairs: Vec<(Value, Value)> = keys.into_iter().zip(vec).collect();
+        pairs.sort_by(|(a, _), (b, _)| self.keys.element.compare(a, b));
+        let sorted: ValueVec = pairs.into_iter().map(|(_, e)| e).collect();
+        sorted.store(&self.vec)
+    }
+}
+
+// (vec-transpose rows) for a vec of vecs, failing unless all rows have the same length.
+// When every row is empty the result is empty as well.
+struct Transpose {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    row: Arc<VecSort>,
+}
+
+impl PrimitiveLike for Transpose {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.vec.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let rows: Vec<ValueVec> = ValueVec::load(&self.vec, &values[0])
+            .iter()
+            .map(|row| ValueVec::load(&self.row, row))
+            .collect();
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            return None;
+        }
+        let columns = (0..width)
+            .map(|i| {
+                rows.iter()
+                    .map(|row| row[i])
+                    .collect::<ValueVec>()
+                    .store(&self.row)
+            })
+            .collect::<Option<ValueVec>>()?;
+        columns.store(&self.vec)
+    }
+}
+
+// (vec-span pred vec) returns (pair prefix suffix), where prefix is the longest
+// prefix whose elements all satisfy pred
+struct VecSpan {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    halves: Arc<PairSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for VecSpan {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.vec.clone(), self.halves.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let mut prefix = ValueVec::load(&self.vec, &values[1]);
+        // The predicate is not called on anything after the first failing element
+        let mut split = prefix.len();
+        for (i, e) in prefix.iter().enumerate() {
+            let result = self.fn_.apply(&values[0], &[*e], egraph).ok()?;
+            if !bool::load(&BoolSort, &result) {
+                split = i;
+                break;
+            }
+        }
+        let suffix = prefix.split_off(split);
+        (prefix.store(&self.vec)?, suffix.store(&self.vec)?).store(&self.halves)
+    }
+}
+
+// Running maximum or minimum of the prefixes of a vec, using `Sort::compare`.
+// An element replaces the running value only if it compares as `keep` against it.
+struct Cumulative {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    keep: Ordering,
+}
+
+impl PrimitiveLike for Cumulative {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.vec.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let mut vec = ValueVec::load(&self.vec, &values[0]);
+        for i in 1..vec.len() {
+            if self.vec.element.compare(&vec[i], &vec[i - 1]) != self.keep {
+                vec[i] = vec[i - 1];
+            }
+        }
+        vec.store(&self.vec)
+    }
+}
+
+// (vec-reduce <function> <vec>), fails on an empty vec
+struct Reduce {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for Reduce {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.vec.clone(), self.vec.element()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let vec = ValueVec::load(&self.vec, &values[1]);
+        let mut elements = vec.into_iter();
+        let first = elements.next()?;
+        elements.try_fold(first, |acc, e| {
+            self.fn_.apply(&values[0], &[acc, e], egraph).ok()
+        })
+    }
+}
+
+// (vec-chunk-reduce <n> <function> <init> <vec>) folds each consecutive chunk of n
+// elements, starting from init, into a vec of the results. The last chunk may be
+// shorter, and this fails unless n is positive.
+struct ChunkReduce {
+    name: Symbol,
+    fn_: Arc<FunctionSort>,
+    vec: Arc<VecSort>,
+    output: Arc<VecSort>,
+}
+
+impl PrimitiveLike for ChunkReduce {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                Arc::new(I64Sort),
+                self.fn_.clone(),
+                self.fn_.output.clone(),
+                self.vec.clone(),
+                self.output.clone(),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let n = usize::try_from(i64::load(&I64Sort, &values[0]))
+            .ok()
+            .filter(|n| *n > 0)?;
+        let vec = ValueVec::load(&self.vec, &values[3]);
+        let results: ValueVec = vec
+            .chunks(n)
+            .map(|chunk| {
+                chunk.iter().try_fold(values[2], |acc, e| {
+                    self.fn_.apply(&values[1], &[acc, *e], egraph).ok()
+                })
+            })
+            .collect::<Option<_>>()?;
+        results.store(&self.output)
+    }
+}
+
+// (apply-trace <function> <x> <n>) returns [x, f(x), ..., f^n(x)], fails if n is negative
+struct ApplyTrace {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for ApplyTrace {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.fn_.clone(),
+                self.vec.element(),
+                Arc::new(I64Sort),
+                self.vec.clone(),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let n = usize::try_from(i64::load(&I64Sort, &values[2])).ok()?;
+        // `n` comes from the program, so the trace grows as the steps succeed
+        // rather than being allocated up front
+        let mut trace = vec![values[1]];
+        for _ in 0..n {
+            let last = *trace.last().unwrap();
+            trace.push(self.fn_.apply(&values[0], &[last], egraph).ok()?);
+        }
+        trace.store(&self.vec)
+    }
+}
+
+// (string-join-map sep fn vec) renders each element with `fn` and joins the
+// results, only interning the final string.
+struct StringJoinMap {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for StringJoinMap {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                Arc::new(StringSort),
+                self.fn_.clone(),
+                self.vec.clone(),
+                Arc::new(StringSort),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let sep = Symbol::load(&StringSort, &values[0]);
+        let vec = ValueVec::load(&self.vec, &values[2]);
+        let mut joined = String::new();
+        for (i, e) in vec.iter().enumerate() {
+            if i > 0 {
+                joined.push_str(sep.as_str());
+            }
+            let part = self.fn_.apply(&values[1], &[*e], egraph).ok()?;
+            joined.push_str(Symbol::load(&StringSort, &part).as_str());
+        }
+        Symbol::from(joined).store(&StringSort)
+    }
+}
+
+/// Fisher-Yates shuffle driven by a SplitMix64 generator, so that the same
+/// seed always produces the same permutation regardless of platform.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+    for i in (1..items.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+// (rational-sum-map f vec) is the exact sum of (f e) over the elements of vec
+struct RationalSumMap {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for RationalSumMap {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.vec.clone(), Arc::new(BigRatSort)],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let vec = ValueVec::load(&self.vec, &values[1]);
+        let mut sum = BigRational::zero();
+        for e in vec {
+            let term = self.fn_.apply(&values[0], &[e], egraph).ok()?;
+            sum += BigRational::load(&BigRatSort, &term);
+        }
+        sum.store(&BigRatSort)
+    }
+}
+
+// (vec-flat-map-indexed f vec) concatenates (f i e) for each element e at index i
+struct FlatMapIndexed {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    output: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for FlatMapIndexed {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.vec.clone(), self.output.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let vec = ValueVec::load(&self.vec, &values[1]);
+        let mut flattened = ValueVec::new();
+        for (i, e) in vec.into_iter().enumerate() {
+            let index = (i as i64).store(&I64Sort)?;
+            let part = self.fn_.apply(&values[0], &[index, e], egraph).ok()?;
+            flattened.extend(ValueVec::load(&self.output, &part));
+        }
+        flattened.store(&self.output)
+    }
+}
+
+/// `(fn-agree-on f g sample)` applies both `f` and `g` to every element of `sample`
+/// and returns whether they produced the same (canonical) value each time.
+struct FnAgreeOn {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for FnAgreeOn {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![
+                self.fn_.clone(),
+                self.fn_.clone(),
+                self.vec.clone(),
+                Arc::new(BoolSort),
+            ],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let vec = ValueVec::load(&self.vec, &values[2]);
+        for e in vec {
+            let f = self.fn_.apply(&values[0], &[e], egraph).ok()?;
+            let g = self.fn_.apply(&values[1], &[e], egraph).ok()?;
+            if egraph.find(&self.fn_.output, f) != egraph.find(&self.fn_.output, g) {
+                return false.store(&BoolSort);
+            }
+        }
+        true.store(&BoolSort)
+    }
+}
+
+/// `(unstable-can-apply? f args)` returns whether `f` takes exactly as many args as
+/// there are elements in `args`, all of the element sort. Rules can use it to guard
+/// `unstable-app` calls built from a vec of args.
+struct CanApply {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for CanApply {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.vec.clone(), Arc::new(BoolSort)],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let n_args = ValueVec::load(&self.vec, &values[1]).len();
+        let element = self.vec.element_name();
+        self.fn_
+            .arg_sorts(n_args)
+            .is_some_and(|sorts| sorts.iter().all(|sort| sort.name() == element))
+            .store(&BoolSort)
+    }
+}
+
+/// `(fn-capture-sorts f)` returns the names of the sorts of the partial args of `f`,
+/// as stored alongside them when the function value was created.
+struct CaptureSorts {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for CaptureSorts {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let names: Option<ValueVec> = self
+            .fn_
+            .inner_values(&values[0])
+            .into_iter()
+            .map(|(sort, _)| sort.name().store(&StringSort))
+            .collect();
+        names?.store(&self.vec)
+    }
+}
+
+/// `(unstable-fn-args f)` returns the partial args of `f`. Partial args can be of any
+/// sort, so this fails unless they are all elements of the vec.
+struct FnArgs {
+    name: Symbol,
+    vec: Arc<VecSort>,
+    fn_: Arc<FunctionSort>,
+}
+
+impl PrimitiveLike for FnArgs {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.vec.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let element = self.vec.element_name();
+        let args: Option<ValueVec> = self
+            .fn_
+            .inner_values(&values[0])
+            .into_iter()
+            .map(|(sort, value)| (sort.name() == element).then_some(value))
+            .collect();
+        args?.store(&self.vec)
+    }
+}
+
+/// `(unstable-app-all f args)` applies `f` to every element of `args`, returning the
+/// vec of results. Unlike mapping with `unstable-app` in a rule, this makes all the
+/// calls within one primitive.
+struct AppAll {
+    name: Symbol,
+    fn_: Arc<FunctionSort>,
+    input: Arc<VecSort>,
+    output: Arc<VecSort>,
+}
+
+impl PrimitiveLike for AppAll {
+    fn name(&self) -> Symbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn TypeConstraint> {
+        SimpleTypeConstraint::new(
+            self.name(),
+            vec![self.fn_.clone(), self.input.clone(), self.output.clone()],
+            span.clone(),
+        )
+        .into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph =
+            egraph.unwrap_or_else(|| panic!("`{}` is not supported yet in facts.", self.name));
+        let args = ValueVec::load(&self.input, &values[1]);
+        let mut results = ValueVec::with_capacity(args.len());
+        for arg in args {
+            let mut result = self.fn_.apply(&values[0], &[arg], egraph).ok()?;
+            // A call may merge e-classes, so keep the results canonical
+            self.fn_.output.canonicalize(&mut result, &egraph.unionfind);
+            results.push(result);
+        }
+        results.store(&self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vec_make_expr() {
+        let mut egraph = EGraph::default();
+        let outputs = egraph
+            .parse_and_run_program(
+                None,
+                r#"
+            (sort IVec (Vec i64))
+            (let v0 (vec-empty))
+            (let v1 (vec-of 1 2 3 4))
+            (extract v0)
+            (extract v1)
+            "#,
+            )
+            .unwrap();
+
+        // Check extracted expr is parsed as an original expr
+        egraph
+            .parse_and_run_program(
+                None,
+                &format!(
+                    r#"
+                (check (= v0 {}))
+                (check (= v1 {}))
+                "#,
+                    outputs[0], outputs[1],
+                ),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_shuffle_is_deterministic_permutation() {
+        let original: Vec<i64> = (0..20).collect();
+
+        let mut a = original.clone();
+        let mut b = original.clone();
+        shuffle(&mut a, 7);
+        shuffle(&mut b, 7);
+        assert_eq!(a, b);
+        assert_ne!(a, original);
+
+        a.sort();
+        assert_eq!(a, original);
     }
 }
@@ -21,6 +21,14 @@ pub struct TypeInfo {
     pub primitives: HashMap<Symbol, Vec<Primitive>>,
     pub func_types: HashMap<Symbol, FuncType>,
     pub global_types: HashMap<Symbol, ArcSort>,
+    /// The first sort declared with each container sort and its args, as written.
+    /// Container sorts written in place of a sort name reuse these, see
+    /// [`TypeInfo::declare_nested_sort`].
+    container_decls: HashMap<(Symbol, Vec<String>), ArcSort>,
+    pub(crate) fn_intern_observer: InternObserverSlot,
+    /// Whether `unstable-fn` with a literal name that is neither a function nor a
+    /// primitive is a type error, instead of creating a value that fails when applied.
+    pub strict_fn_names: bool,
 }
 
 impl Default for TypeInfo {
@@ -32,6 +40,9 @@ impl Default for TypeInfo {
             primitives: Default::default(),
             func_types: Default::default(),
             global_types: Default::default(),
+            container_decls: Default::default(),
+            fn_intern_observer: Default::default(),
+            strict_fn_names: false,
         };
 
         res.add_sort(UnitSort, span!()).unwrap();
@@ -41,14 +52,18 @@ impl Default for TypeInfo {
         res.add_sort(F64Sort, span!()).unwrap();
         res.add_sort(BigIntSort, span!()).unwrap();
         res.add_sort(BigRatSort, span!()).unwrap();
+        res.add_sort(StringBuilderSort::default(), span!()).unwrap();
 
         res.add_presort::<MapSort>(span!()).unwrap();
         res.add_presort::<SetSort>(span!()).unwrap();
         res.add_presort::<VecSort>(span!()).unwrap();
         res.add_presort::<FunctionSort>(span!()).unwrap();
         res.add_presort::<MultiSetSort>(span!()).unwrap();
+        res.add_presort::<PairSort>(span!()).unwrap();
+        res.add_presort::<BitVecSort>(span!()).unwrap();
 
         res.add_primitive(ValueEq);
+        res.add_primitive(CanonicalKey);
 
         res
     }
@@ -60,28 +75,71 @@ impl TypeInfo {
     }
 
     /// Adds a sort constructor to the typechecker's known set of types.
+    ///
+    /// Fails if one of its reserved primitives is already reserved by another sort
+    /// constructor or declared as a primitive.
     pub fn add_presort<S: Presort>(&mut self, span: Span) -> Result<(), TypeError> {
         let name = S::presort_name();
-        match self.presorts.entry(name) {
-            HEntry::Occupied(_) => Err(TypeError::SortAlreadyBound(name, span)),
-            HEntry::Vacant(e) => {
-                e.insert(S::make_sort);
-                self.reserved_primitives.extend(S::reserved_primitives());
-                Ok(())
-            }
+        if self.presorts.contains_key(&name) {
+            return Err(TypeError::SortAlreadyBound(name, span));
         }
+        let reserved = S::reserved_primitives();
+        if let Some(conflict) = reserved.iter().find(|primitive| {
+            self.reserved_primitives.contains(*primitive)
+                || self.primitives.contains_key(*primitive)
+        }) {
+            return Err(TypeError::ReservedPrimitiveConflict(*conflict, name, span));
+        }
+        self.presorts.insert(name, S::make_sort);
+        self.reserved_primitives.extend(reserved);
+        Ok(())
     }
 
     pub fn add_arcsort(&mut self, sort: ArcSort, span: Span) -> Result<(), TypeError> {
         let name = sort.name();
 
-        match self.sorts.entry(name) {
-            HEntry::Occupied(_) => Err(TypeError::SortAlreadyBound(name, span)),
-            HEntry::Vacant(e) => {
-                e.insert(sort.clone());
-                sort.register_primitives(self);
-                Ok(())
+        if self.sorts.contains_key(&name) {
+            return Err(TypeError::SortAlreadyBound(name, span));
+        }
+        self.check_eq_primitive_name(name, &sort, &span)?;
+        self.sorts.insert(name, sort.clone());
+        sort.clone().register_primitives(self);
+        self.add_eq_primitive(name, sort);
+        Ok(())
+    }
+
+    /// Declares `name` as another name for `sort`. Aliases of container sorts get their
+    /// own equality primitive, unless it is named the same for every alias.
+    fn add_alias(&mut self, name: Symbol, sort: ArcSort, span: Span) -> Result<(), TypeError> {
+        if self.sorts.contains_key(&name) {
+            return Err(TypeError::SortAlreadyBound(name, span));
+        }
+        self.check_eq_primitive_name(name, &sort, &span)?;
+        self.sorts.insert(name, sort.clone());
+        if sort.eq_primitive_name(name) != sort.eq_primitive_name(sort.name()) {
+            self.add_eq_primitive(name, sort);
+        }
+        Ok(())
+    }
+
+    /// Fails if the equality primitive of `sort` declared as `name` is already a function.
+    fn check_eq_primitive_name(
+        &self,
+        name: Symbol,
+        sort: &ArcSort,
+        span: &Span,
+    ) -> Result<(), TypeError> {
+        match sort.eq_primitive_name(name) {
+            Some(eq) if self.func_types.contains_key(&eq) => {
+                Err(TypeError::FunctionAlreadyBound(eq, span.clone()))
             }
+            _ => Ok(()),
+        }
+    }
+
+    fn add_eq_primitive(&mut self, name: Symbol, sort: ArcSort) {
+        if let Some(eq) = sort.eq_primitive_name(name) {
+            self.add_primitive(ContainerEq { name: eq, sort });
         }
     }
 
@@ -312,6 +370,9 @@ impl TypeInfo {
                 span.clone(),
                 Box::new(self.typecheck_schedule(symbol_gen, schedule)?),
             ),
+            Schedule::Adaptive(span, rulesets) => {
+                ResolvedSchedule::Adaptive(span.clone(), rulesets.clone())
+            }
             Schedule::Run(span, RunConfig { ruleset, until }) => {
                 let until = until
                     .as_ref()
@@ -344,14 +405,58 @@ impl TypeInfo {
         let sort = match presort_and_args {
             Some((presort, args)) => {
                 if let Some(mksort) = self.presorts.get(presort) {
-                    mksort(self, name, args)?
+                    let sort = mksort(self, name, args)?;
+                    self.container_decls
+                        .entry(Self::container_decl_key(*presort, args))
+                        .or_insert_with(|| sort.clone());
+                    sort
                 } else {
                     return Err(TypeError::PresortNotFound(*presort, span));
                 }
             }
             None => Arc::new(EqSort { name }),
         };
-        self.add_arcsort(sort, span)
+        // Function sorts with the same signature share one sort, so that their
+        // values are interchangeable. The later names are aliases for the first.
+        let alias = sort
+            .clone()
+            .as_arc_any()
+            .downcast::<FunctionSort>()
+            .ok()
+            .and_then(|function| {
+                self.get_sort_by(|existing: &Arc<FunctionSort>| existing.same_signature(&function))
+            });
+        match alias {
+            Some(alias) => self.add_alias(name, alias, span),
+            None => self.add_arcsort(sort, span),
+        }
+    }
+
+    fn container_decl_key(presort: Symbol, args: &[Expr]) -> (Symbol, Vec<String>) {
+        (presort, args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    /// Resolve a container sort written in place of a sort name, like the `(Vec i64)` in
+    /// `(UnstableFn ((Vec i64)) i64)`, to a sort called `name`. If a sort was already
+    /// declared as the same container sort with the same args, `name` is an alias for it,
+    /// so values of either can be used for the other.
+    pub fn declare_nested_sort(
+        &mut self,
+        name: Symbol,
+        presort: Symbol,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<ArcSort, TypeError> {
+        if let Some(existing) = self
+            .container_decls
+            .get(&Self::container_decl_key(presort, args))
+            .cloned()
+        {
+            self.add_alias(name, existing.clone(), span)?;
+            return Ok(existing);
+        }
+        self.declare_sort(name, &Some((presort, args.to_vec())), span)?;
+        Ok(self.sorts[&name].clone())
     }
 
     fn typecheck_rule(
@@ -557,10 +662,14 @@ pub enum TypeError {
     Unbound(Symbol, Span),
     #[error("{1}\nUndefined sort {0}")]
     UndefinedSort(Symbol, Span),
+    #[error("{2}\nWidth {1} does not match sort {}", .0.name())]
+    WidthMismatch(ArcSort, i64, Span),
     #[error("{2}\nSort {0} definition is disallowed: {1}")]
     DisallowedSort(Symbol, String, Span),
     #[error("{1}\nUnbound function {0}")]
     UnboundFunction(Symbol, Span),
+    #[error("{1}\n{0} is not a constructor")]
+    NotConstructor(Symbol, Span),
     #[error("{1}\nFunction already bound {0}")]
     FunctionAlreadyBound(Symbol, Span),
     #[error("{1}\nSort {0} already declared.")]
@@ -569,6 +678,8 @@ pub enum TypeError {
     PrimitiveAlreadyBound(Symbol, Span),
     #[error("Function type mismatch: expected {} => {}, actual {} => {}", .1.iter().map(|s| s.name().to_string()).collect::<Vec<_>>().join(", "), .0.name(), .3.iter().map(|s| s.name().to_string()).collect::<Vec<_>>().join(", "), .2.name())]
     FunctionTypeMismatch(ArcSort, Vec<ArcSort>, ArcSort, Vec<ArcSort>),
+    #[error("{2}\nPrimitive {0} of presort {1} is already reserved or declared.")]
+    ReservedPrimitiveConflict(Symbol, Symbol, Span),
     #[error("{1}\nPresort {0} not found.")]
     PresortNotFound(Symbol, Span),
     #[error("{}\nFailed to infer a type for: {0}", .0.span())]
@@ -1,24 +1,75 @@
 //! Baseline union-find implementation without sizes or ranks, using path
 //! halving for compression.
 //!
-//! This implementation uses interior mutability for `find`.
+//! This implementation uses interior mutability for `find`. With the `parallel`
+//! feature, parents are atomics, so that `find` can be called from several threads
+//! at once: path halving only ever points an id at one of its ancestors, so
+//! concurrent finds stay correct.
 use crate::util::HashMap;
 use crate::{Symbol, Value};
 
+#[cfg(not(feature = "parallel"))]
 use std::cell::Cell;
 use std::fmt::Debug;
 use std::mem;
+#[cfg(feature = "parallel")]
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub type Id = u64;
 
 #[derive(Debug, Clone, Default)]
 pub struct UnionFind {
-    parents: Vec<Cell<Id>>,
+    parents: Vec<Parent>,
     n_unions: usize,
     recent_ids: HashMap<Symbol, Vec<Id>>,
     staged_ids: HashMap<Symbol, Vec<Id>>,
 }
 
+/// The parent of an id, which is only atomic with the `parallel` feature.
+#[derive(Debug)]
+struct Parent(
+    #[cfg(not(feature = "parallel"))] Cell<Id>,
+    #[cfg(feature = "parallel")] AtomicU64,
+);
+
+impl Parent {
+    #[cfg(not(feature = "parallel"))]
+    fn new(id: Id) -> Self {
+        Self(Cell::new(id))
+    }
+
+    #[cfg(feature = "parallel")]
+    fn new(id: Id) -> Self {
+        Self(AtomicU64::new(id))
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn get(&self) -> Id {
+        self.0.get()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn get(&self) -> Id {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn set(&self, id: Id) {
+        self.0.set(id)
+    }
+
+    #[cfg(feature = "parallel")]
+    fn set(&self, id: Id) {
+        self.0.store(id, Ordering::Relaxed)
+    }
+}
+
+impl Clone for Parent {
+    fn clone(&self) -> Self {
+        Self::new(self.get())
+    }
+}
+
 impl UnionFind {
     /// The number of unions that have been performed over the lifetime of this
     /// data-structure.
@@ -26,10 +77,15 @@ impl UnionFind {
         self.n_unions
     }
 
+    /// The number of [`Id`]s created so far.
+    pub fn num_ids(&self) -> usize {
+        self.parents.len()
+    }
+
     /// Create a fresh [`Id`].
     pub fn make_set(&mut self) -> Id {
         let res = self.parents.len() as u64;
-        self.parents.push(Cell::new(res));
+        self.parents.push(Parent::new(res));
         res
     }
 
@@ -74,11 +130,12 @@ impl UnionFind {
         let mut cur = self.parent(id);
         loop {
             let next = self.parent(cur.get());
-            if cur.get() == next.get() {
-                return cur.get();
+            let next_id = next.get();
+            if cur.get() == next_id {
+                return next_id;
             }
             // Path halving
-            let grand = self.parent(next.get());
+            let grand = self.parent(next_id);
             cur.set(grand.get());
             cur = grand;
         }
@@ -93,7 +150,7 @@ impl UnionFind {
         debug_assert_eq!(val1.tag, val2.tag);
 
         Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: val1.tag,
             bits: self.union(val1.bits, val2.bits, sort),
         }
@@ -132,7 +189,7 @@ impl UnionFind {
         }
     }
 
-    fn parent(&self, id: Id) -> &Cell<Id> {
+    fn parent(&self, id: Id) -> &Parent {
         &self.parents[id as usize]
     }
 }
@@ -141,8 +198,12 @@ impl UnionFind {
 mod tests {
     use super::*;
 
-    fn ids(us: impl IntoIterator<Item = Id>) -> Vec<Cell<Id>> {
-        us.into_iter().map(Cell::new).collect()
+    fn ids(us: impl IntoIterator<Item = Id>) -> Vec<Id> {
+        us.into_iter().collect()
+    }
+
+    fn parents(uf: &UnionFind) -> Vec<Id> {
+        uf.parents.iter().map(Parent::get).collect()
     }
 
     #[test]
@@ -155,7 +216,7 @@ mod tests {
         }
 
         // test the initial condition of everyone in their own set
-        assert_eq!(uf.parents, ids(0..n));
+        assert_eq!(parents(&uf), ids(0..n));
 
         // build up one set
         uf.union_raw(0, 1);
@@ -174,6 +235,6 @@ mod tests {
 
         // indexes:         0, 1, 2, 3, 4, 5, 6, 7, 8, 9
         let expected = vec![0, 0, 0, 0, 4, 5, 6, 6, 6, 6];
-        assert_eq!(uf.parents, ids(expected));
+        assert_eq!(parents(&uf), ids(expected));
     }
 }
@@ -5,15 +5,16 @@ use lazy_static::lazy_static;
 
 use crate::ast::Symbol;
 
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "value-tags"))]
 use crate::{BoolSort, F64Sort, I64Sort, Sort, StringSort};
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 // FIXME this shouldn't be pub
 pub struct Value {
     // since egglog is type-safe, we don't need to store the tag
-    // however, it is useful in debugging, so we keep it in debug builds
-    #[cfg(debug_assertions)]
+    // however, it is useful in debugging, so we keep it in debug builds,
+    // and in release builds with the `value-tags` feature
+    #[cfg(any(debug_assertions, feature = "value-tags"))]
     pub tag: Symbol,
     pub bits: u64,
 }
@@ -26,15 +27,24 @@ lazy_static! {
 impl Value {
     pub fn unit() -> Self {
         Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: *UNIT,
             bits: 0,
         }
     }
 
+    /// The name of the sort this value belongs to, if values are tagged with their sort.
+    /// That is the case in debug builds and with the `value-tags` feature.
+    pub fn sort_name(&self) -> Option<Symbol> {
+        #[cfg(any(debug_assertions, feature = "value-tags"))]
+        return Some(self.tag);
+        #[cfg(not(any(debug_assertions, feature = "value-tags")))]
+        return None;
+    }
+
     pub fn fake() -> Self {
         Value {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: *BOGUS,
             bits: 1234567890,
         }
@@ -44,7 +54,7 @@ impl Value {
 impl From<i64> for Value {
     fn from(i: i64) -> Self {
         Self {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: I64Sort.name(),
             bits: i as u64,
         }
@@ -54,9 +64,9 @@ impl From<i64> for Value {
 impl From<OrderedFloat<f64>> for Value {
     fn from(f: OrderedFloat<f64>) -> Self {
         Self {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: F64Sort.name(),
-            bits: f.into_inner().to_bits(),
+            bits: crate::sort::f64_to_bits(f.into_inner()),
         }
     }
 }
@@ -64,7 +74,7 @@ impl From<OrderedFloat<f64>> for Value {
 impl From<Symbol> for Value {
     fn from(s: Symbol) -> Self {
         Self {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: StringSort.name(),
             bits: NonZeroU32::from(s).get().into(),
         }
@@ -74,7 +84,7 @@ impl From<Symbol> for Value {
 impl From<bool> for Value {
     fn from(b: bool) -> Self {
         Self {
-            #[cfg(debug_assertions)]
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
             tag: BoolSort.name(),
             bits: b as u64,
         }
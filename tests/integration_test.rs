@@ -1,4 +1,13 @@
-use egglog::{ast::Expr, *};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use egglog::{
+    ast::{Expr, Span},
+    sort::*,
+    *,
+};
 use symbol_table::GlobalSymbol;
 
 #[test]
@@ -433,6 +442,21 @@ fn test_value_to_classid() {
     assert_eq!(value, egraph.class_id_to_value(&class_id));
 }
 
+#[test]
+fn test_bitvec_width_mismatch() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(None, "(sort BV8 (UnstableBitVec 8))")
+        .unwrap();
+    let err = egraph
+        .parse_and_run_program(None, "(let x (bv 1 4))")
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Width 4 does not match sort BV8"),
+        "{err}"
+    );
+}
+
 #[test]
 fn test_serialize_subsume_status() {
     let mut egraph = EGraph::default();
@@ -469,3 +493,936 @@ fn test_serialize_subsume_status() {
     assert!(serialized.nodes[&a_id].subsumed);
     assert!(!serialized.nodes[&b_id].subsumed);
 }
+
+#[test]
+fn test_serialize_function_node_id_round_trip() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64) (math-neg Math))
+            (math-neg (Num 1))
+            "#,
+        )
+        .unwrap();
+
+    let serialized = egraph.serialize(SerializeConfig::default());
+    for name in ["Num", "math-neg"] {
+        let node = egglog::SerializedNode::Function {
+            name: name.into(),
+            offset: 0,
+        };
+        let node_id = egraph.to_node_id(None, node.clone());
+        assert!(serialized.nodes.contains_key(&node_id), "{node_id}");
+        assert_eq!(egraph.from_node_id(&node_id), node);
+    }
+}
+
+#[test]
+fn test_set_eq_container_sort() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Elem (E i64))
+            (sort ElemSet (Set Elem))
+            (sort IntSet (Set i64))
+            "#,
+        )
+        .unwrap();
+
+    let elem_set = egraph
+        .get_sort_by(|s: &Arc<SetSort>| s.element_name() == "Elem".into())
+        .unwrap();
+    let int_set = egraph
+        .get_sort_by(|s: &Arc<SetSort>| s.element_name() == I64Sort.name())
+        .unwrap();
+    assert!(elem_set.is_container_sort() && elem_set.is_eq_container_sort());
+    assert!(int_set.is_container_sort() && !int_set.is_eq_container_sort());
+}
+
+#[test]
+fn test_diff_since_snapshot() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64) (Add Math Math))
+            (sort MathFn (UnstableFn (Math) Math))
+            ; an alias of MathFn, whose values should only be listed once
+            (sort MathToMath (UnstableFn (Math) Math))
+            (let a (Num 1))
+            (let b (Num 2))
+            "#,
+        )
+        .unwrap();
+    let baseline = egraph.snapshot();
+    assert!(egraph.diff(&baseline).is_empty());
+
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (let add-a (unstable-fn "Add" a))
+            (union a b)
+            "#,
+        )
+        .unwrap();
+    let diff = egraph.diff(&baseline);
+
+    let (closure_sort, closure) = egraph
+        .eval_expr(&Expr::Var(span!(), "add-a".into()))
+        .unwrap();
+    assert_eq!(diff.new_entries.len(), 1);
+    assert_eq!(diff.new_entries[0].0.name(), closure_sort.name());
+    assert_eq!(diff.new_entries[0].1, closure);
+
+    let (math, a) = egraph.eval_expr(&Expr::Var(span!(), "a".into())).unwrap();
+    assert_eq!(diff.new_unions.len(), 1);
+    assert_eq!(diff.new_unions[0].1, egraph.find(&math, a).bits);
+    assert!(diff.new_eclasses.is_empty());
+}
+
+#[test]
+fn test_pop_truncates_interned() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64) (Add Math Math))
+            (sort MathFn (UnstableFn (Math) Math))
+            (sort MathVec (Vec Math))
+            (let a (Num 1))
+            (let add-a (unstable-fn "Add" a))
+            (let as (vec-of a))
+            (let greeting (sb-push (sb-new) "hello"))
+            "#,
+        )
+        .unwrap();
+    let fn_sort = egraph.get_sort::<FunctionSort>().unwrap();
+    let vec_sort = egraph.get_sort::<VecSort>().unwrap();
+    let sb_sort = egraph.get_sort::<StringBuilderSort>().unwrap();
+    let interned = || {
+        (
+            fn_sort.num_interned(),
+            vec_sort.num_interned(),
+            sb_sort.num_interned(),
+        )
+    };
+    let before = interned();
+
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (push)
+            (let add-b (unstable-fn "Add" (Num 2)))
+            (let bs (vec-of (Num 2)))
+            (let farewell (sb-push (sb-push (sb-new) "bye") "!"))
+            ; canonicalizes the values from before the push
+            (union (Num 2) a)
+            (run 1)
+            "#,
+        )
+        .unwrap();
+    let after = interned();
+    assert!(after.0 > before.0);
+    assert!(after.1 > before.1);
+    assert!(after.2 > before.2);
+
+    egraph.parse_and_run_program(None, "(pop)").unwrap();
+    assert_eq!(interned(), before);
+    // The values from before the push still load
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (let sum (unstable-app add-a (Num 3)))
+            (check (= sum (Add (Num 1) (Num 3))))
+            (check (= (vec-get as 0) (Num 1)))
+            (check (= (sb-build greeting) "hello"))
+            "#,
+        )
+        .unwrap();
+}
+
+#[test]
+fn test_apply_function_value() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (sort IntFn (UnstableFn (i64) i64))
+            (let add-two (unstable-fn "+" 2))
+            "#,
+        )
+        .unwrap();
+    let (fn_sort, add_two) = egraph
+        .eval_expr(&Expr::Var(span!(), "add-two".into()))
+        .unwrap();
+    let (i64_sort, five) = egraph.eval_expr(&Expr::Lit(span!(), 5.into())).unwrap();
+
+    let seven = egraph
+        .apply_function_value(&fn_sort, add_two, &[five])
+        .unwrap();
+    assert_eq!(i64::load(&I64Sort, &seven), 7);
+
+    assert!(matches!(
+        egraph.apply_function_value(&fn_sort, add_two, &[five, five]),
+        Err(Error::FunctionValueArity(_, 1, 2))
+    ));
+    assert!(matches!(
+        egraph.apply_function_value(&i64_sort, five, &[five]),
+        Err(Error::NotFunctionSort(_))
+    ));
+}
+
+#[test]
+fn test_function_sort_iter_values() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (sort IntFn (UnstableFn (i64) i64))
+            (let add-two (unstable-fn "+" 2))
+            (let times-three (unstable-fn "*" 3))
+            "#,
+        )
+        .unwrap();
+    let fn_sort = egraph.get_sort::<FunctionSort>().unwrap();
+    let values: Vec<_> = fn_sort.iter_values().collect();
+    assert_eq!(values.len(), 2);
+    assert_eq!(values[0].1, "+".into());
+    assert_eq!(values[1].1, "*".into());
+    assert_eq!(values[1].2.len(), 1);
+    assert_eq!(i64::load(&I64Sort, &values[1].2[0].1), 3);
+
+    // Creating the same function value again finds the interned one
+    let (_, times_three) = egraph
+        .parse_and_run_program(None, r#"(let times-three-again (unstable-fn "*" 3))"#)
+        .and_then(|_| egraph.eval_expr(&Expr::Var(span!(), "times-three-again".into())))
+        .unwrap();
+    assert_eq!(times_three, values[1].0);
+    assert_eq!(fn_sort.iter_values().count(), 2);
+}
+
+#[test]
+fn test_function_sort_signature_matches() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64) (Add Math Math) (Neg Math))
+            (sort MathFn (UnstableFn (Math) Math))
+            "#,
+        )
+        .unwrap();
+    let fn_sort = egraph.get_sort::<FunctionSort>().unwrap();
+    let typeinfo = egraph.type_info();
+    assert!(fn_sort.signature_matches("Neg".into(), 0, typeinfo));
+    assert!(fn_sort.signature_matches("Add".into(), 1, typeinfo));
+    // wrong number of captured args
+    assert!(!fn_sort.signature_matches("Add".into(), 0, typeinfo));
+    assert!(!fn_sort.signature_matches("Neg".into(), 1, typeinfo));
+    // wrong input sort
+    assert!(!fn_sort.signature_matches("Num".into(), 0, typeinfo));
+    // primitives are not declared functions
+    assert!(!fn_sort.signature_matches("+".into(), 1, typeinfo));
+}
+
+#[test]
+fn test_fn_intern_observer() {
+    use std::sync::Mutex;
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let mut egraph = EGraph::default();
+    let recorded = events.clone();
+    egraph.set_fn_intern_observer(Some(Box::new(move |name, captured_count| {
+        recorded
+            .lock()
+            .unwrap()
+            .push((name.to_string(), captured_count))
+    })));
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (sort IntFn (UnstableFn (i64) i64))
+            (let add-two (unstable-fn "+" 2))
+            (let neg (unstable-fn "-" 0))
+            ; interning an existing value again is not an event
+            (let add-two-again (unstable-fn "+" 2))
+            "#,
+        )
+        .unwrap();
+    assert_eq!(
+        *events.lock().unwrap(),
+        [("+".to_string(), 1), ("-".to_string(), 1)]
+    );
+
+    egraph.set_fn_intern_observer(None);
+    egraph
+        .parse_and_run_program(None, r#"(let add-three (unstable-fn "+" 3))"#)
+        .unwrap();
+    assert_eq!(events.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_display_function_value() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64) (Add Math Math))
+            (function pick (i64 String i64) i64 :no-merge)
+            (sort IntFn (UnstableFn (i64) i64))
+            (sort MathFn (UnstableFn (Math) Math))
+            (let f (unstable-fn "pick" 1 "hi"))
+            (let one (Num 1))
+            (let add-one (unstable-fn "Add" one))
+            (union one (Num 2))
+            "#,
+        )
+        .unwrap();
+    let (fn_sort, f) = egraph.eval_expr(&Expr::Var(span!(), "f".into())).unwrap();
+    assert_eq!(egraph.display_value(&fn_sort, f), r#"pick[1, "hi"]"#);
+
+    // Captured e-class ids are shown by their canonical leader
+    let (math, one) = egraph.eval_expr(&Expr::Var(span!(), "one".into())).unwrap();
+    let (math_fn_sort, add_one) = egraph
+        .eval_expr(&Expr::Var(span!(), "add-one".into()))
+        .unwrap();
+    let leader = egraph.find(&math, one).bits;
+    assert_eq!(
+        egraph.display_value(&math_fn_sort, add_one),
+        format!("Add[Math-{leader}]")
+    );
+}
+
+#[test]
+fn test_reserved_primitives() {
+    let egraph = EGraph::default();
+    let reserved = egraph.reserved_primitives();
+    for name in [
+        "unstable-fn",
+        "unstable-app",
+        "vec-of",
+        "set-of",
+        "map-insert",
+    ] {
+        assert!(reserved.contains(&name.into()), "{name} is not reserved");
+    }
+    // declared primitives such as `+` are not presort reservations
+    assert!(!reserved.contains(&"+".into()));
+    assert!(reserved.windows(2).all(|w| w[0].as_str() < w[1].as_str()));
+}
+
+/// A sort constructor `(Interval T)` defined outside the crate, whose values are
+/// pairs of bounds of sort `T`.
+#[derive(Debug)]
+struct IntervalSort {
+    name: GlobalSymbol,
+    bound: ArcSort,
+    intervals: std::sync::Mutex<Vec<(Value, Value)>>,
+}
+
+impl IntervalSort {
+    fn store(&self, interval: (Value, Value)) -> Value {
+        let mut intervals = self.intervals.lock().unwrap();
+        let i = match intervals.iter().position(|i| *i == interval) {
+            Some(i) => i,
+            None => {
+                intervals.push(interval);
+                intervals.len() - 1
+            }
+        };
+        Value {
+            #[cfg(any(debug_assertions, feature = "value-tags"))]
+            tag: self.name,
+            bits: i as u64,
+        }
+    }
+
+    fn load(&self, value: &Value) -> (Value, Value) {
+        self.intervals.lock().unwrap()[value.bits as usize]
+    }
+}
+
+impl Presort for IntervalSort {
+    fn presort_name() -> GlobalSymbol {
+        "Interval".into()
+    }
+
+    fn reserved_primitives() -> Vec<GlobalSymbol> {
+        vec!["interval".into(), "interval-lo".into()]
+    }
+
+    fn make_sort(
+        typeinfo: &mut TypeInfo,
+        name: GlobalSymbol,
+        args: &[Expr],
+    ) -> Result<ArcSort, TypeError> {
+        let [Expr::Var(span, bound)] = args else {
+            panic!("Interval takes the sort of its bounds");
+        };
+        let bound = typeinfo
+            .sorts
+            .get(bound)
+            .ok_or(TypeError::UndefinedSort(*bound, span.clone()))?;
+        Ok(Arc::new(IntervalSort {
+            name,
+            bound: bound.clone(),
+            intervals: Default::default(),
+        }))
+    }
+}
+
+impl Sort for IntervalSort {
+    fn name(&self) -> GlobalSymbol {
+        self.name
+    }
+
+    fn as_arc_any(self: Arc<Self>) -> Arc<dyn std::any::Any + Send + Sync + 'static> {
+        self
+    }
+
+    fn register_primitives(self: Arc<Self>, typeinfo: &mut TypeInfo) {
+        typeinfo.add_primitive(IntervalPrimitive {
+            name: "interval".into(),
+            interval: self.clone(),
+        });
+        typeinfo.add_primitive(IntervalPrimitive {
+            name: "interval-lo".into(),
+            interval: self,
+        });
+    }
+
+    fn extract_term(
+        &self,
+        _egraph: &EGraph,
+        value: Value,
+        extractor: &extract::Extractor,
+        termdag: &mut TermDag,
+    ) -> Option<(extract::Cost, Term)> {
+        let (lo, hi) = self.load(&value);
+        let (lo_cost, lo) = extractor.find_best(lo, termdag, &self.bound)?;
+        let (hi_cost, hi) = extractor.find_best(hi, termdag, &self.bound)?;
+        Some((
+            lo_cost + hi_cost,
+            termdag.app("interval".into(), vec![lo, hi]),
+        ))
+    }
+}
+
+/// `(interval lo hi)` makes an interval and `(interval-lo i)` gets its lower bound.
+struct IntervalPrimitive {
+    name: GlobalSymbol,
+    interval: Arc<IntervalSort>,
+}
+
+impl PrimitiveLike for IntervalPrimitive {
+    fn name(&self) -> GlobalSymbol {
+        self.name
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn constraint::TypeConstraint> {
+        let bound = self.interval.bound.clone();
+        let sorts = if self.name.as_str() == "interval" {
+            vec![bound.clone(), bound, self.interval.clone()]
+        } else {
+            vec![self.interval.clone(), bound]
+        };
+        constraint::SimpleTypeConstraint::new(self.name, sorts, span.clone()).into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        match values {
+            [lo, hi] => Some(self.interval.store((*lo, *hi))),
+            [interval] => Some(self.interval.load(interval).0),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_custom_presort() {
+    let mut egraph = EGraph::default();
+    egraph.add_presort::<IntervalSort>(span!()).unwrap();
+    assert!(egraph.reserved_primitives().contains(&"interval".into()));
+    let outputs = egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (sort IntRange (Interval i64))
+            (let r (interval 1 5))
+            (check (= (interval-lo r) 1))
+            (check (= r (interval 1 5)))
+            (check (!= r (interval 1 6)))
+            (extract r)
+            "#,
+        )
+        .unwrap();
+    assert_eq!(outputs, vec!["(interval 1 5)"]);
+    // the presort can't be added twice
+    assert!(matches!(
+        egraph.add_presort::<IntervalSort>(span!()),
+        Err(TypeError::SortAlreadyBound(..))
+    ));
+}
+
+/// Sort constructors that are only registered, to test reserving their primitives.
+macro_rules! reserving_presort {
+    ($presort:ident, $name:literal, [$($primitive:literal),*]) => {
+        struct $presort;
+
+        impl Presort for $presort {
+            fn presort_name() -> GlobalSymbol {
+                $name.into()
+            }
+
+            fn reserved_primitives() -> Vec<GlobalSymbol> {
+                vec![$($primitive.into()),*]
+            }
+
+            fn make_sort(
+                _typeinfo: &mut TypeInfo,
+                _name: GlobalSymbol,
+                _args: &[Expr],
+            ) -> Result<ArcSort, TypeError> {
+                unreachable!()
+            }
+        }
+    };
+}
+
+reserving_presort!(FirstApp, "FirstApp", ["my-app"]);
+reserving_presort!(SecondApp, "SecondApp", ["my-other-app", "my-app"]);
+reserving_presort!(BuiltinApp, "BuiltinApp", ["unstable-app"]);
+reserving_presort!(PlusApp, "PlusApp", ["+"]);
+
+#[test]
+fn test_reserved_primitive_conflicts() {
+    let mut egraph = EGraph::default();
+    let conflict = |result| match result {
+        Err(TypeError::ReservedPrimitiveConflict(primitive, presort, _)) => {
+            Some((primitive.to_string(), presort.to_string()))
+        }
+        _ => None,
+    };
+    egraph.add_presort::<FirstApp>(span!()).unwrap();
+    assert_eq!(
+        conflict(egraph.add_presort::<SecondApp>(span!())),
+        Some(("my-app".into(), "SecondApp".into()))
+    );
+    // reserved by the built-in `UnstableFn`
+    assert_eq!(
+        conflict(egraph.add_presort::<BuiltinApp>(span!())),
+        Some(("unstable-app".into(), "BuiltinApp".into()))
+    );
+    // declared by the built-in `i64`
+    assert_eq!(
+        conflict(egraph.add_presort::<PlusApp>(span!())),
+        Some(("+".into(), "PlusApp".into()))
+    );
+    // a failed registration reserves nothing
+    let reserved = egraph.reserved_primitives();
+    assert!(reserved.contains(&"my-app".into()));
+    assert!(!reserved.contains(&"my-other-app".into()));
+}
+
+#[test]
+fn test_value_sort_name() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64))
+            (let one (Num 1))
+            (let two 2)
+            "#,
+        )
+        .unwrap();
+    for name in ["one", "two"] {
+        let (sort, value) = egraph.eval_expr(&Expr::Var(span!(), name.into())).unwrap();
+        if cfg!(any(debug_assertions, feature = "value-tags")) {
+            assert_eq!(value.sort_name(), Some(sort.name()));
+        } else {
+            assert_eq!(value.sort_name(), None);
+        }
+    }
+}
+
+#[test]
+fn test_extract_all_of_sort() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64) (Add Math Math) (Mul Math Math))
+            (let sum (Add (Num 1) (Num 2)))
+            (let product (Mul sum (Num 2)))
+            (union (Num 3) sum)
+            "#,
+        )
+        .unwrap();
+    let (termdag, terms) = egraph.extract_all_of_sort("Math".into()).unwrap();
+
+    let mut exported: Vec<String> = terms
+        .iter()
+        .map(|(_, id)| termdag.to_string(termdag.get(*id)))
+        .collect();
+    exported.sort();
+    assert_eq!(
+        exported,
+        ["(Mul (Num 3) (Num 2))", "(Num 1)", "(Num 2)", "(Num 3)"]
+    );
+
+    // `(Num 2)` and `(Num 3)` are shared with `(Mul (Num 3) (Num 2))`, so the DAG holds
+    // the three literals, the three numbers and the product
+    assert_eq!(termdag.size(), 7);
+
+    assert!(egraph.extract_all_of_sort("Unknown".into()).is_err());
+}
+
+#[test]
+fn test_extract_dag() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype M
+                (Leaf i64)
+                (Wrap M)
+                (Pair M M)
+                (Triple M M M)
+                (Tag M))
+            (sort MFn (UnstableFn (M) M))
+            (let shared (Wrap (Wrap (Wrap (Leaf 1)))))
+            (let pair (Pair shared shared))
+            (union pair (Tag (Wrap (Wrap (Wrap (Wrap (Wrap (Leaf 2))))))))
+            (let f (unstable-fn "Triple" shared shared))
+            "#,
+        )
+        .unwrap();
+    let expr = |name: &str| Expr::Var(span!(), name.into());
+
+    // As a tree, the pair costs 11 and the tag 8, but as a DAG the pair only costs 6
+    let (sort, value) = egraph.eval_expr(&expr("pair")).unwrap();
+    let mut termdag = TermDag::default();
+    let (cost, term) = egraph.extract(value, &mut termdag, &sort).unwrap();
+    assert_eq!(cost, 8);
+    assert!(termdag.to_string(&term).starts_with("(Tag "));
+    let (cost, term) = egraph.extract_dag(value, &mut termdag, &sort).unwrap();
+    assert_eq!(cost, 6);
+    assert_eq!(
+        termdag.to_string(&term),
+        "(Pair (Wrap (Wrap (Wrap (Leaf 1)))) (Wrap (Wrap (Wrap (Leaf 1)))))"
+    );
+
+    // The function value counts its repeated partial arg once
+    let (sort, value) = egraph.eval_expr(&expr("f")).unwrap();
+    let (cost, _) = egraph.extract(value, &mut termdag, &sort).unwrap();
+    assert_eq!(cost, 11);
+    let (cost, _) = egraph.extract_dag(value, &mut termdag, &sort).unwrap();
+    assert_eq!(cost, 7);
+}
+
+/// Squares an i64, counting how often it was called.
+struct CountedSquare {
+    calls: Arc<AtomicUsize>,
+}
+
+impl PrimitiveLike for CountedSquare {
+    fn name(&self) -> GlobalSymbol {
+        "counted-square".into()
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn constraint::TypeConstraint> {
+        let sorts: Vec<ArcSort> = vec![Arc::new(I64Sort), Arc::new(I64Sort)];
+        constraint::SimpleTypeConstraint::new(self.name(), sorts, span.clone()).into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        _egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let x = i64::load(&I64Sort, &values[0]);
+        (x * x).store(&I64Sort)
+    }
+}
+
+#[test]
+fn test_apply_memo_into() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut egraph = EGraph::default();
+    egraph.add_primitive(CountedSquare {
+        calls: calls.clone(),
+    });
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (sort IntFn (UnstableFn (i64) i64))
+            (function squares (i64) i64 :no-merge)
+            (function lengths (String) i64 :no-merge)
+            (let square (unstable-fn "counted-square"))
+            (let a (apply-memo-into square 3 "squares"))
+            (let b (apply-memo-into square 3 "squares"))
+            (let c (apply-memo-into square 4 "squares"))
+            (check (= a 9) (= b 9) (= c 16))
+            (check (= (squares 3) 9) (= (squares 4) 16))
+
+            ;; rows already in the table are used instead of calling the function
+            (set (squares 5) 0)
+            (let d (apply-memo-into square 5 "squares"))
+            (check (= d 0))
+
+            ;; the table must exist and map i64 to i64
+            (fail (let e (apply-memo-into square 6 "lengths")))
+            (fail (let f (apply-memo-into square 6 "missing")))
+            "#,
+        )
+        .unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_unstable_app_memo() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut egraph = EGraph::default();
+    egraph.add_primitive(CountedSquare {
+        calls: calls.clone(),
+    });
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64))
+            (sort IntFn (UnstableFn (i64) i64))
+            (let square (unstable-fn "counted-square"))
+            (let a (unstable-app-memo square 3))
+            (let b (unstable-app-memo square 3))
+            (let c (unstable-app-memo square 4))
+            (check (= a 9) (= b 9) (= c 16))
+            "#,
+        )
+        .unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    // Rebuilds that change nothing keep the results
+    egraph.parse_and_run_program(None, "(run 1)").unwrap();
+    egraph
+        .parse_and_run_program(None, "(let d (unstable-app-memo square 3))")
+        .unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    // but ones after a union forget them
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (union (Num 1) (Num 2))
+            (run 1)
+            (let e (unstable-app-memo square 3))
+            (check (= e 9))
+            "#,
+        )
+        .unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn test_unstable_fn_table_many() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (sort Shift (UnstableFn (i64) i64))
+            (sort Row (Vec i64))
+            (sort Rows (Vec Row))
+            (sort Shifts (Vec Shift))
+            (function shift (i64 i64) i64 :no-merge)
+            (set (shift 999 1) 1000)
+            "#,
+        )
+        .unwrap();
+    let fn_sort = egraph.get_sort::<FunctionSort>().unwrap();
+    let before = fn_sort.intern_stats();
+
+    let rows: Vec<String> = (0..1000).map(|i| format!("(vec-of {i})")).collect();
+    egraph
+        .parse_and_run_program(
+            None,
+            &format!(
+                r#"
+                (let shifts (unstable-fn-table "shift" (vec-of {})))
+                (check (= (vec-length shifts) 1000))
+                (let last (unstable-app (vec-get shifts 999) 1))
+                (check (= last 1000))
+                "#,
+                rows.join(" ")
+            ),
+        )
+        .unwrap();
+    let after = fn_sort.intern_stats();
+    assert_eq!(after.interned, before.interned + 1000);
+    assert_eq!(after.dedup_hits, before.dedup_hits);
+}
+
+#[test]
+fn test_sort_info() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (datatype Math (Num i64))
+            (sort MathFn (UnstableFn (Math i64) Math))
+            (sort Transform (UnstableFn (Math i64) Math))
+            (sort Nums (Vec i64))
+            "#,
+        )
+        .unwrap();
+    let sorts = egraph.list_sorts();
+    for name in ["Math", "MathFn", "Transform", "Nums", "i64"] {
+        assert!(sorts.contains(&name.into()), "missing {name}");
+    }
+    assert!(sorts.windows(2).all(|w| w[0].as_str() < w[1].as_str()));
+
+    let info = egraph.sort_info("MathFn".into()).unwrap();
+    assert_eq!(
+        info,
+        SortInfo {
+            name: "MathFn".into(),
+            is_eq_sort: false,
+            is_container_sort: true,
+            is_eq_container_sort: true,
+            element_sorts: vec!["Math".into(), "i64".into(), "Math".into()],
+            kind: SortKind::Function {
+                inputs: vec!["Math".into(), "i64".into()],
+                output: "Math".into(),
+                variadic: false,
+            },
+        }
+    );
+    // An alias reports the sort it names
+    assert_eq!(egraph.sort_info("Transform".into()), Some(info));
+
+    let info = egraph.sort_info("Math".into()).unwrap();
+    assert!(info.is_eq_sort && !info.is_container_sort);
+    assert_eq!(info.kind, SortKind::Other);
+    let info = egraph.sort_info("Nums".into()).unwrap();
+    assert_eq!(info.element_sorts, vec![GlobalSymbol::from("i64")]);
+    assert!(egraph.sort_info("Missing".into()).is_none());
+}
+
+/// Adds to an i64 by making a function value of the same sort it is called through,
+/// and applying that, to check that applying a function value may re-enter the e-graph.
+struct AddViaFunction;
+
+impl PrimitiveLike for AddViaFunction {
+    fn name(&self) -> GlobalSymbol {
+        "add-via-fn".into()
+    }
+
+    fn get_type_constraints(&self, span: &Span) -> Box<dyn constraint::TypeConstraint> {
+        let sorts: Vec<ArcSort> = vec![Arc::new(I64Sort), Arc::new(I64Sort), Arc::new(I64Sort)];
+        constraint::SimpleTypeConstraint::new(self.name(), sorts, span.clone()).into_box()
+    }
+
+    fn apply(
+        &self,
+        values: &[Value],
+        _sorts: (&[ArcSort], &ArcSort),
+        egraph: Option<&mut EGraph>,
+    ) -> Option<Value> {
+        let egraph = egraph?;
+        let amount = i64::load(&I64Sort, &values[0]);
+        let make = Expr::Call(
+            span!(),
+            "unstable-fn".into(),
+            vec![
+                Expr::Lit(span!(), GlobalSymbol::from("+").into()),
+                Expr::Lit(span!(), amount.into()),
+            ],
+        );
+        let (sort, add) = egraph.eval_expr(&make).ok()?;
+        egraph.apply_function_value(&sort, add, &values[1..]).ok()
+    }
+}
+
+#[test]
+fn test_apply_reenters_same_sort() {
+    let mut egraph = EGraph::default();
+    egraph.add_primitive(AddViaFunction);
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (sort IntFn (UnstableFn (i64) i64))
+            (let add-two (unstable-fn "add-via-fn" 2))
+            (let five (unstable-app add-two 3))
+            (check (= five 5))
+            ;; nested twice, through a function value that applies another one
+            (let twice (unstable-fn "unstable-app" add-two))
+            (let seven (unstable-app twice 5))
+            (check (= seven 7))
+            "#,
+        )
+        .unwrap();
+    // One for each function value made, and one for the `+` the primitive made
+    let fn_sort = egraph.get_sort::<FunctionSort>().unwrap();
+    assert_eq!(fn_sort.intern_stats().interned, 3);
+}
+
+#[test]
+fn test_adaptive_backs_off_rulesets_without_new_facts() {
+    let mut egraph = EGraph::default();
+    egraph
+        .parse_and_run_program(
+            None,
+            r#"
+            (relation cnt (i64))
+            (relation seen ())
+
+            (ruleset grow)
+            (rule ((cnt x) (< x 32)) ((cnt (+ x 1))) :ruleset grow)
+
+            ; matches on every run, but only adds a new fact the first time
+            (ruleset noisy)
+            (rule () ((seen)) :ruleset noisy)
+
+            (cnt 0)
+            (run-schedule (adaptive grow noisy))
+            (check (cnt 32))
+            "#,
+        )
+        .unwrap();
+
+    let report = egraph.get_overall_run_report();
+    let runs = |ruleset: &str| report.num_matches_per_ruleset[&GlobalSymbol::from(ruleset)];
+    // grow adds a fact in each of its 32 productive runs
+    assert_eq!(runs("grow"), 32);
+    // noisy matches once per run, and backs off after the first
+    assert!(runs("noisy") < 10, "noisy ran {} times", runs("noisy"));
+}